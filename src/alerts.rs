@@ -0,0 +1,130 @@
+use crate::app::AppData;
+use crate::data::{LookupStatus, PlayerStats, Tag};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const TOAST_DURATION: Duration = Duration::from_secs(15);
+const DEFAULT_SOUND_FILE: &str = "/usr/share/sounds/freedesktop/stereo/bell.oga";
+
+#[derive(Debug)]
+pub struct AlertSettings {
+  pub on_blacklisted: bool,
+  pub on_sniper: bool,
+  pub on_threat: bool,
+  pub threat_threshold: f64,
+  pub on_nicked: bool,
+
+  pub sound: bool,
+  pub toast: bool,
+  pub desktop: bool,
+}
+
+impl Default for AlertSettings {
+  fn default() -> Self {
+    Self {
+      on_blacklisted: true,
+      on_sniper: true,
+      on_threat: false,
+      threat_threshold: 1000.,
+      on_nicked: false,
+      sound: false,
+      toast: true,
+      desktop: false,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Toast {
+  pub message: String,
+  pub created: Instant,
+}
+
+/// Returns every alert rule the player matches, e.g. "blacklisted (Cheater)"
+fn matched_rules(data: &AppData, player: &PlayerStats) -> Vec<String> {
+  let settings = &data.settings.alerts;
+  let mut rules = vec![];
+
+  if settings.on_blacklisted {
+    if let Some(entry) = player.uuid.as_ref().and_then(|uuid| data.lists.get(uuid)) {
+      if entry.reason.is_blacklist() {
+        rules.push(format!("blacklisted ({})", entry.reason.name()));
+      }
+    }
+  }
+
+  if settings.on_sniper && player.tag() == Some(Tag::Sniper) {
+    rules.push("tagged SNIPER".to_string());
+  }
+
+  if settings.on_threat && player.threat_score() > settings.threat_threshold {
+    rules.push(format!("threat score {:.0}", player.threat_score()));
+  }
+
+  if settings.on_nicked && player.status == LookupStatus::Nicked {
+    rules.push("nicked".to_string());
+  }
+
+  rules
+}
+
+/// Checks the alert rules for a player that just joined the lobby and fires the enabled alerts
+pub fn alert_on_join(data: &mut AppData, player: &PlayerStats) {
+  if data.is_own(&player.username) || data.is_in_party(&player.username) {
+    return;
+  }
+
+  let rules = matched_rules(data, player);
+
+  if rules.is_empty() {
+    return;
+  }
+
+  let message = format!("{} joined: {}", player.username, rules.join(", "));
+  println!("Alert: {}", message);
+
+  let settings = &data.settings.alerts;
+
+  if settings.sound {
+    play_sound();
+  }
+
+  if settings.desktop {
+    send_desktop_notification(&message);
+  }
+
+  if settings.toast {
+    data.alerts.push(Toast {
+      message,
+      created: Instant::now(),
+    });
+  }
+}
+
+fn play_sound() {
+  let sound_file = crate::get_string_setting("alert_sound", DEFAULT_SOUND_FILE);
+
+  if cfg!(target_os = "linux") {
+    run_in_background(Command::new("paplay").arg(sound_file));
+  }
+}
+
+fn send_desktop_notification(message: &str) {
+  // notify-send talks to the notification daemon over dbus
+  if cfg!(target_os = "linux") {
+    run_in_background(Command::new("notify-send").args(["--app-name=Jahbo", "Jahbo", message]));
+  }
+}
+
+fn run_in_background(command: &mut Command) {
+  match command.spawn() {
+    // wait in another thread, so the child doesn't stay around as a zombie
+    Ok(mut child) => {
+      thread::spawn(move || child.wait());
+    }
+    Err(e) => {
+      dbg!(e);
+    }
+  }
+}
@@ -1,14 +1,30 @@
+use crate::alerts::{AlertSettings, Toast, TOAST_DURATION};
 use crate::data;
-use crate::data::PlayerStats;
+use crate::data::{LobbySummary, LookupStatus, PlayerStats, StatusCheck, Tag};
+use crate::events;
+use crate::fetching;
+use crate::game::{GameResult, Location, MatchState, Team};
+use crate::history::MatchHistory;
+use crate::keys;
+use crate::keys::KeyStatus;
+use crate::lists::{ListReason, PlayerLists};
+use crate::lookup::{LookupPurpose, LookupService, PlayerLoaded, StatusLoaded};
+use crate::parsing::{LobbyEvent, LogEvent, ParserHealth};
+use crate::session;
+use crate::session::{Session, SessionTracker};
 use eframe::egui;
 use eframe::egui::RichText;
 use eframe::epaint::Color32;
 use itertools::Itertools;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver};
 use std::thread;
+use std::time::Instant;
 
 const PAUSED_BY_DEFAULT: bool = !cfg!(debug_assertions); // should be true in release
 const DEFAULT_FONT_SIZE: f32 = 14.;
+const STATUS_HOVER_TEXT: &str =
+  "Checks if the player is online and what they're playing. Players can hide this, then they show as offline.";
 
 fn get_rank_color_and_name(rank: &str, donator_rank: &str, monthly_rank: &str) -> (Color32, String) {
   match (rank, donator_rank, monthly_rank) {
@@ -24,6 +40,23 @@ fn get_rank_color_and_name(rank: &str, donator_rank: &str, monthly_rank: &str) -
   }
 }
 
+pub fn get_username_override() -> Option<String> {
+  crate::get_optional_string_setting("username")
+}
+
+fn get_team_color(team: Team) -> Color32 {
+  match team {
+    Team::Red => Color32::from_rgb(255, 85, 85),
+    Team::Blue => Color32::from_rgb(85, 85, 255),
+    Team::Green => Color32::from_rgb(85, 255, 85),
+    Team::Yellow => Color32::from_rgb(255, 255, 85),
+    Team::Aqua => Color32::from_rgb(85, 255, 255),
+    Team::White => Color32::WHITE,
+    Team::Pink => Color32::from_rgb(255, 85, 255),
+    Team::Gray => Color32::from_rgb(85, 85, 85),
+  }
+}
+
 #[derive(Debug)]
 pub struct AppSettings {
   pub paused: bool,
@@ -33,6 +66,8 @@ pub struct AppSettings {
   pub auto_clear_on_who: bool,
   pub auto_tile: bool,
   pub auto_sort: bool,
+  pub auto_clear_on_game_end: bool,
+  pub alerts: AlertSettings,
 }
 
 impl Default for AppSettings {
@@ -45,6 +80,8 @@ impl Default for AppSettings {
       auto_clear_on_who: true,
       auto_tile: false,
       auto_sort: false,
+      auto_clear_on_game_end: true,
+      alerts: Default::default(),
     }
   }
 }
@@ -53,37 +90,141 @@ impl Default for AppSettings {
 pub struct AppData {
   pub players: Vec<PlayerStats>,
   pub settings: AppSettings,
+  pub lists: PlayerLists,
+  pub alerts: Vec<Toast>,
+  pub own_username: Option<String>,
+  pub own_stats: Option<PlayerStats>,
+  pub party: Vec<String>, // the other members of our own party
+  pub match_state: MatchState,
+  pub session: SessionTracker,
+  pub location: Location,
+  pub history: MatchHistory,
+  pub last_event_source: Option<String>, // the log file the last event was read from
+  pub pending_events: Vec<LogEvent>,     // read while paused
+  pub parser_heartbeat: Option<Instant>, // the last time the log parser went through its loop
+  pub parser_crash: Option<String>,      // why the log parser crashed, until it's running again
+  pub own_stats_requested: Option<Instant>,
+  pub statuses: HashMap<String, StatusCheck>, // online status by uuid, checked on request
+  pub lobby_lookups: HashSet<String>,         // lowercase names of players in the lobby whose stats are loading
+}
+
+impl AppData {
+  pub fn is_own(&self, username: &str) -> bool {
+    self
+      .own_username
+      .as_ref()
+      .is_some_and(|own| own.to_lowercase() == username.to_lowercase())
+  }
+
+  pub fn is_in_party(&self, username: &str) -> bool {
+    self.party.iter().any(|p| p.to_lowercase() == username.to_lowercase())
+  }
+
+  /// Players are grouped as: own player, party members, everyone else
+  pub fn player_group(&self, username: &str) -> u8 {
+    if self.is_own(username) {
+      0
+    } else if self.is_in_party(username) {
+      1
+    } else {
+      2
+    }
+  }
+
+  pub fn has_player(&self, username: &str) -> bool {
+    self
+      .players
+      .iter()
+      .any(|p| p.username.to_lowercase() == username.to_lowercase())
+  }
+
+  /// Also forgets the lookups which are still loading, so those players aren't added afterwards
+  pub fn clear_players(&mut self) {
+    self.players.clear();
+    self.lobby_lookups.clear();
+  }
+
+  pub fn add_player(&mut self, player: PlayerStats) {
+    if let Some(uuid) = &player.uuid {
+      self.lists.update_username(uuid, &player.username);
+    }
+
+    println!("Added {}", player.username);
+
+    let group = self.player_group(&player.username);
+    let index = self
+      .players
+      .iter()
+      .position(|p| self.player_group(&p.username) > group)
+      .unwrap_or(self.players.len());
+
+    self.players.insert(index, player);
+  }
+}
+
+#[derive(Debug)]
+struct ListEditor {
+  uuid: String,
+  username: String,
+  reason: ListReason,
+  note: String,
 }
 
 #[derive(Debug)]
 pub struct App {
-  data: Arc<Mutex<AppData>>,
+  data: AppData, // owned by the ui, the other threads send their results over channels
+  lobby_events: Receiver<LobbyEvent>,
+  loaded_players: Receiver<PlayerLoaded>,
+  loaded_statuses: Receiver<StatusLoaded>,
+  lookups: LookupService,
 
   player_add_text: String,
   font_size: f32,
   space_scalar: f32,
+  list_editor: Option<ListEditor>,
+  show_lists: bool,
+  show_sessions: bool,
+  show_history: bool,
+  history_export_status: Option<String>,
 }
 
-impl Default for App {
-  fn default() -> Self {
-    Self {
+impl App {
+  pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    let (lookups, loaded_players, loaded_statuses) = LookupService::start();
+
+    let (events_sender, lobby_events) = channel();
+    thread::spawn(|| crate::parsing::supervise_parser(events_sender));
+
+    let mut app = Self {
       data: Default::default(),
+      lobby_events,
+      loaded_players,
+      loaded_statuses,
+      lookups,
       player_add_text: Default::default(),
       font_size: DEFAULT_FONT_SIZE,
       space_scalar: 1.,
+      list_editor: None,
+      show_lists: false,
+      show_sessions: false,
+      show_history: false,
+      history_export_status: None,
+    };
+
+    app.data.lists = PlayerLists::load();
+    app.data.session = SessionTracker::load();
+    app.data.history = MatchHistory::load();
+    keys::load_keys();
+    thread::spawn(fetching::keep_validating_api_keys);
+
+    // the username from settings.toml overrides the one detected from the log
+    if let Some(username) = get_username_override() {
+      events::load_own_stats(&mut app.data, &username, &app.lookups);
     }
-  }
-}
 
-impl App {
-  pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-    let app = Self::default();
-    let data_arc = app.data.clone();
     _cc.egui_ctx.send_viewport_cmd(egui::ViewportCommand::Maximized(true));
     _cc.egui_ctx.set_visuals(egui::Visuals::dark()); // dark theme
 
-    thread::spawn(|| crate::parsing::start_parsing_logs(data_arc));
-
     app
   }
   fn small_text(&self, text: &str, color: Color32) -> RichText {
@@ -96,11 +237,60 @@ impl App {
 
 impl eframe::App for App {
   fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-    let data = self.data.lock().unwrap();
+    for event in self.lobby_events.try_iter() {
+      events::handle_lobby_event(&mut self.data, event, &self.lookups);
+    }
+    for loaded in self.loaded_players.try_iter() {
+      events::handle_player_loaded(&mut self.data, loaded);
+    }
+    for loaded in self.loaded_statuses.try_iter() {
+      events::handle_status_loaded(&mut self.data, loaded);
+    }
+    events::refresh_own_stats(&mut self.data, &self.lookups);
+
+    // taken out for the frame, so the ui code can borrow self and the data at the same time
+    let mut data = std::mem::take(&mut self.data);
+    self.show(ctx, &mut data);
+    self.data = data;
+  }
+}
 
+impl App {
+  fn show(&mut self, ctx: &egui::Context, data: &mut AppData) {
     let mut should_tile = data.settings.auto_tile;
 
-    drop(data);
+    if let Some(warning) = keys::get_warning() {
+      let color = if warning.is_blocking() {
+        Color32::LIGHT_RED
+      } else {
+        Color32::YELLOW
+      };
+
+      egui::TopBottomPanel::top("api_key_panel").show(ctx, |ui| {
+        ui.label(self.big_text(&warning.message(), color));
+      });
+    }
+
+    data.alerts.retain(|toast| toast.created.elapsed() < TOAST_DURATION);
+
+    if !data.alerts.is_empty() {
+      let mut alerts_to_dismiss: Vec<usize> = vec![];
+
+      egui::TopBottomPanel::top("alerts_panel").show(ctx, |ui| {
+        for (index, toast) in data.alerts.iter().enumerate() {
+          ui.horizontal(|ui| {
+            if ui.button("Dismiss").clicked() {
+              alerts_to_dismiss.push(index);
+            }
+            ui.label(self.small_text(&toast.message, Color32::LIGHT_RED));
+          });
+        }
+      });
+
+      for index in alerts_to_dismiss.into_iter().rev() {
+        data.alerts.remove(index);
+      }
+    }
 
     let left_panel = egui::SidePanel::left("left_panel")
       .resizable(false)
@@ -123,48 +313,42 @@ impl eframe::App for App {
               {
                 player_add_text_response.request_focus();
 
-                let data = self.data.lock().unwrap();
-
                 let username = self.player_add_text.trim();
                 // dont add a player that is an empty string or is already added
-                if !username.is_empty()
-                  && !data
-                    .players
-                    .iter()
-                    .any(|p| p.username.to_lowercase() == username.to_lowercase())
-                {
-                  drop(data);
-
-                  let player = data::get_stats(username); // takes some time
-
-                  let mut data = self.data.lock().unwrap();
-
-                  data.players.push(player);
+                if !username.is_empty() && !data.has_player(username) {
+                  // added when the stats are loaded
+                  self.lookups.lookup(username, None, LookupPurpose::Manual);
                   self.player_add_text.clear();
-
-                  if data.settings.auto_sort {
-                    drop(data);
-                    data::sort_players(self.data.clone());
-                  }
                 }
               }
 
-              let mut data = self.data.lock().unwrap();
-
               if ui
                 .button("Remove all players")
                 .on_hover_text("Will remove all players.")
                 .clicked()
               {
-                data.players.clear();
+                data.clear_players();
               }
             });
             ui.add_space(10.);
 
-            let mut data = self.data.lock().unwrap();
-
             ui.checkbox(&mut data.settings.paused, "Paused")
-              .on_hover_text("Will not change any data automatically while paused.");
+              .on_hover_text("Will not change any data automatically while paused. New log events are queued until resumed.");
+            if !data.pending_events.is_empty() {
+              ui.label(format!("{} pending events", data.pending_events.len()));
+              ui.horizontal(|ui| {
+                if ui
+                  .add_enabled(!data.settings.paused, egui::Button::new("Apply"))
+                  .on_hover_text("Apply the queued events, in the order they were read")
+                  .clicked()
+                {
+                  events::apply_pending_events(data, &self.lookups);
+                }
+                if ui.button("Discard").on_hover_text("Forget the queued events").clicked() {
+                  data.pending_events.clear();
+                }
+              });
+            }
             ui.checkbox(&mut data.settings.auto_join_active, "Auto join")
               .on_hover_text("If someone joins a bedwars lobby, it will add them");
             ui.checkbox(&mut data.settings.auto_leave_active, "Auto leave")
@@ -173,15 +357,110 @@ impl eframe::App for App {
               .on_hover_text("On /who it will add all the players which are not already added");
             ui.checkbox(&mut data.settings.auto_clear_on_who, "Auto clear on who")
               .on_hover_text("On /who it will first remove all the players");
+            ui.checkbox(&mut data.settings.auto_clear_on_game_end, "Auto clear on game end")
+              .on_hover_text("When a game ends, it will remove all the players");
             ui.checkbox(&mut data.settings.auto_tile, "Auto tile")
               .on_hover_text("Windows will always be tiled in a grid pattern");
             if ui.checkbox(&mut data.settings.auto_sort, "Auto order")
               .on_hover_text("Players will be sorted everytime they are added/removed. Auto tile should be turned on (if not, nothing will update until tiled manually).").clicked() {
-                drop(data);
-                data::sort_players(self.data.clone());
-              } else {
-                drop(data);
+                data::sort_players(data);
+              }
+
+            ui.add_space(10.);
+
+            match ParserHealth::of(data) {
+              ParserHealth::Running => ui.label("Log parser: running"),
+              ParserHealth::Stalled => ui
+                .label(RichText::new("Log parser: stalled").color(Color32::YELLOW))
+                .on_hover_text("The log files haven't been read in a while"),
+              ParserHealth::Crashed(error) => ui
+                .label(RichText::new("Log parser: crashed").color(Color32::RED))
+                .on_hover_text(format!("{}\nRestarting...", error)),
+            };
+
+            if let Some(source) = &data.last_event_source {
+              ui.label(format!("Last event from: {}", source))
+                .on_hover_text("The log file jahbo last read an event from");
+              ui.add_space(10.);
+            }
+
+            ui.collapsing("Session", |ui| {
+              match &data.session.current {
+                Some(session) => {
+                  for line in get_session_lines(session) {
+                    ui.label(line);
+                  }
+                }
+                None => {
+                  ui.label("Waiting for your own stats");
+                }
               }
+              ui.label(format!(
+                "Seen in the log: {} wins, {} losses",
+                data.session.wins, data.session.losses
+              ))
+              .on_hover_text("Games seen in the log, which show up before the API updates");
+
+              ui.horizontal(|ui| {
+                if ui
+                  .button("Start session")
+                  .on_hover_text("Ends the current session and starts a new one from your current stats")
+                  .clicked()
+                {
+                  events::start_session(data, &self.lookups);
+                }
+                if ui.button("Past sessions").clicked() {
+                  self.show_sessions = !self.show_sessions;
+                }
+              });
+            });
+            ui.add_space(10.);
+
+            if !data.party.is_empty() {
+              ui.label(format!("Party: {}", data.party.join(", ")))
+                .on_hover_text("Party members are grouped after you and left out of the lobby summary and alerts");
+              ui.add_space(10.);
+            }
+
+            let alerts = &mut data.settings.alerts;
+
+            ui.collapsing("Alerts", |ui| {
+              ui.label("Alert when a player joins who is:");
+              ui.checkbox(&mut alerts.on_blacklisted, "Blacklisted");
+              ui.checkbox(&mut alerts.on_sniper, "Tagged SNIPER");
+              ui.checkbox(&mut alerts.on_nicked, "Nicked");
+              ui.checkbox(&mut alerts.on_threat, "Above the threat score")
+                .on_hover_text("Threat score is star * (final kills/deaths)^2");
+              ui.add(egui::DragValue::new(&mut alerts.threat_threshold).speed(10.).clamp_range(0.0..=f64::MAX));
+              ui.add_space(5.);
+              ui.label("Alert with:");
+              ui.checkbox(&mut alerts.toast, "In-app message");
+              ui.checkbox(&mut alerts.sound, "Sound")
+                .on_hover_text("Plays alert_sound from settings.toml (or the freedesktop bell) with paplay");
+              ui.checkbox(&mut alerts.desktop, "Desktop notification")
+                .on_hover_text("Sends a notification to the notification daemon with notify-send");
+            });
+
+            let api_keys = keys::get_keys();
+            if !api_keys.is_empty() {
+              ui.collapsing("API keys", |ui| {
+                for api_key in api_keys {
+                  let color = match api_key.status {
+                    KeyStatus::Valid => Color32::LIGHT_GREEN,
+                    KeyStatus::Invalid => Color32::LIGHT_RED,
+                    KeyStatus::RateLimited => Color32::YELLOW,
+                    KeyStatus::Unused => Color32::GRAY,
+                  };
+                  ui.label(RichText::new(format!("{} {}", api_key.masked(), api_key.status.name())).color(color));
+
+                  let remaining = api_key
+                    .remaining
+                    .map_or(String::new(), |remaining| format!(", {} left", remaining));
+                  ui.label(format!("{} requests{}", api_key.requests, remaining))
+                    .on_hover_text("Requests made with this key, and how many are left until the rate limit resets");
+                }
+              });
+            }
 
             ui.add_space(10.);
             ui.add(egui::Slider::new(&mut self.font_size, 6.0..=40.0).text("Font size"));
@@ -204,21 +483,41 @@ impl eframe::App for App {
               )
               .clicked()
             {
-              data::sort_players(self.data.clone());
+              data::sort_players(data);
 
               should_tile = true;
             }
+
+            if ui
+              .button("Blacklist / whitelist")
+              .on_hover_text("Shows all blacklisted and whitelisted players.")
+              .clicked()
+            {
+              self.show_lists = !self.show_lists;
+            }
+
+            if ui
+              .button("Match history")
+              .on_hover_text("Shows all the games played, with the lobby as it was when the game started.")
+              .clicked()
+            {
+              self.show_history = !self.show_history;
+            }
           });
           ui.add_space(10.);
         });
       });
 
-    let mut data = self.data.lock().unwrap();
+    let summary_panel = egui::TopBottomPanel::top("summary_panel").show(ctx, |ui| {
+      show_lobby_summary(ui, &data::summarize_lobby(data), self);
+    });
 
     let mut players_to_remove: Vec<String> = vec![];
+    let mut player_to_list: Option<(String, String)> = None;
+    let mut status_to_check: Option<String> = None;
 
     let offset_x = left_panel.response.rect.width() as isize + 10;
-    let offset_y = 10;
+    let offset_y = summary_panel.response.rect.bottom() as isize + 10;
     let size_width = (270. * self.space_scalar) as isize;
     let size_height = (330. * self.space_scalar) as isize;
     let width_count = (ctx.available_rect().width() as isize) / (size_width);
@@ -247,30 +546,120 @@ impl eframe::App for App {
           player.bedwars_level.map_or("N/A".to_string(), |x| x.to_string())
         );
 
+        // players who are out of the game are dimmed
+        let is_dead = data.match_state.is_dead(&player.username);
+        let title_color = if is_dead {
+          title_color.gamma_multiply(0.4)
+        } else {
+          title_color
+        };
+
         let title = self.big_text(&title_text, title_color);
 
+        let list_entry = player.uuid.as_ref().and_then(|uuid| data.lists.get(uuid));
+
+        let is_own = data.is_own(&player.username);
+        let is_in_party = data.is_in_party(&player.username);
+        let team = data.match_state.team_of(&player.username);
+
+        let mut frame = egui::Frame::window(&ctx.style());
+        if is_own {
+          frame = frame.stroke(egui::Stroke::new(2., Color32::GOLD));
+        } else if is_in_party {
+          frame = frame.stroke(egui::Stroke::new(2., Color32::LIGHT_BLUE));
+        } else if let Some(entry) = list_entry {
+          let color = if entry.reason.is_blacklist() {
+            Color32::RED
+          } else {
+            Color32::GREEN
+          };
+          frame = frame.stroke(egui::Stroke::new(2., color));
+        } else if let Some(team) = team {
+          // blacklisted players are more important than the team, which is also in the "Team:" label
+          frame = frame.stroke(egui::Stroke::new(2., get_team_color(team)));
+        }
+
         let mut window_is_open = true;
 
+        let mut window = egui::Window::new(title)
+          .resizable(false)
+          .frame(frame)
+          .open(&mut window_is_open);
+
         if should_tile {
-          egui::Window::new(title)
-            .resizable(false)
-            .current_pos((
-              offset_x as f32 + (index as isize % width_count) as f32 * size_width as f32,
-              offset_y as f32 + (index as isize / width_count) as f32 * size_height as f32,
-            ))
-            .open(&mut window_is_open)
-            .show(ctx, |ui| {
-              show_window_content(ui, player, self);
-            });
-        } else {
-          egui::Window::new(title)
-            .resizable(false)
-            .open(&mut window_is_open)
-            .show(ctx, |ui| {
-              show_window_content(ui, player, self);
-            });
+          window = window.current_pos((
+            offset_x as f32 + (index as isize % width_count) as f32 * size_width as f32,
+            offset_y as f32 + (index as isize / width_count) as f32 * size_height as f32,
+          ));
         }
 
+        window.show(ctx, |ui| {
+          if is_dead {
+            ui.set_opacity(0.4);
+          }
+
+          if is_own {
+            ui.label(self.small_text("You", Color32::GOLD));
+          } else if is_in_party {
+            ui.label(self.small_text("Party member", Color32::LIGHT_BLUE));
+          }
+
+          if let Some(team) = team {
+            let bed_text = if data.match_state.destroyed_beds.contains(&team) {
+              " (no bed)"
+            } else {
+              ""
+            };
+            ui.label(self.small_text(&format!("Team: {}{}", team.name(), bed_text), get_team_color(team)));
+          }
+
+          let final_kills = data.match_state.final_kills_of(&player.username);
+          let beds_broken = data.match_state.beds_broken_by(&player.username);
+          if final_kills > 0 || beds_broken > 0 {
+            ui.label(self.small_text(
+              &format!("This game: {} final kills, {} beds", final_kills, beds_broken),
+              Color32::LIGHT_YELLOW,
+            ));
+          }
+
+          if let Some(entry) = list_entry {
+            let (color, list_name) = if entry.reason.is_blacklist() {
+              (Color32::LIGHT_RED, "Blacklisted")
+            } else {
+              (Color32::LIGHT_GREEN, "Whitelisted")
+            };
+            ui.label(self.small_text(
+              &format!("{}: {} ({})", list_name, entry.reason.name(), entry.date),
+              color,
+            ));
+            if !entry.note.is_empty() {
+              ui.label(self.small_text(&format!("Note: {}", entry.note), color));
+            }
+          }
+
+          show_window_content(ui, player, self);
+
+          if let Some(uuid) = &player.uuid {
+            if let Some(status) = data.statuses.get(uuid) {
+              ui.label(self.small_text(&status.description(), Color32::LIGHT_BLUE));
+            }
+
+            ui.add_space(5.);
+            ui.horizontal(|ui| {
+              if ui
+                .button("Blacklist / whitelist")
+                .on_hover_text("Add a note and put this player on the blacklist or whitelist.")
+                .clicked()
+              {
+                player_to_list = Some((uuid.clone(), player.username.clone()));
+              }
+              if ui.button("Status").on_hover_text(STATUS_HOVER_TEXT).clicked() {
+                status_to_check = Some(uuid.clone());
+              }
+            });
+          }
+        });
+
         if !window_is_open {
           players_to_remove.push(player.username.clone());
         }
@@ -285,43 +674,335 @@ impl eframe::App for App {
       }
     }
 
+    if let Some(uuid) = status_to_check {
+      data.statuses.insert(uuid.clone(), StatusCheck::Checking);
+      self.lookups.lookup_status(&uuid);
+    }
+
+    if let Some((uuid, username)) = player_to_list {
+      let (reason, note) = data
+        .lists
+        .get(&uuid)
+        .map_or((ListReason::Cheater, String::new()), |e| (e.reason, e.note.clone()));
+
+      self.list_editor = Some(ListEditor {
+        uuid,
+        username,
+        reason,
+        note,
+      });
+    }
+
+    self.show_list_editor(ctx, &mut data.lists);
+    self.show_lists_window(ctx, &mut data.lists, &mut data.statuses);
+    self.show_sessions_window(ctx, &data.session);
+    self.show_history_window(ctx, &data.history);
+
     ctx.request_repaint();
   }
 }
 
-fn show_window_content(ui: &mut egui::Ui, player: &PlayerStats, app: &App) {
-  if player.no_data {
-    ui.label(app.small_text("Not a real username. Probably nicked", Color32::WHITE));
-    return;
+impl App {
+  fn show_list_editor(&mut self, ctx: &egui::Context, lists: &mut PlayerLists) {
+    let Some(editor) = &mut self.list_editor else {
+      return;
+    };
+
+    let mut is_open = true;
+    let mut is_done = false;
+
+    egui::Window::new(format!("List {}", editor.username))
+      .resizable(false)
+      .collapsible(false)
+      .open(&mut is_open)
+      .show(ctx, |ui| {
+        egui::ComboBox::from_label("Reason")
+          .selected_text(editor.reason.name())
+          .show_ui(ui, |ui| {
+            for reason in ListReason::ALL {
+              ui.selectable_value(&mut editor.reason, reason, reason.name());
+            }
+          });
+
+        ui.label("Note");
+        ui.text_edit_multiline(&mut editor.note);
+
+        ui.horizontal(|ui| {
+          if ui.button("Save").clicked() {
+            lists.set(&editor.uuid, &editor.username, editor.reason, editor.note.trim());
+            is_done = true;
+          }
+          if lists.get(&editor.uuid).is_some() && ui.button("Remove from list").clicked() {
+            lists.remove(&editor.uuid);
+            is_done = true;
+          }
+        });
+      });
+
+    if !is_open || is_done {
+      self.list_editor = None;
+    }
+  }
+
+  fn show_sessions_window(&mut self, ctx: &egui::Context, session: &SessionTracker) {
+    egui::Window::new("Sessions")
+      .open(&mut self.show_sessions)
+      .show(ctx, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+          for session in session.current.iter().chain(session.past.iter().rev()) {
+            ui.label(
+              RichText::new(format!(
+                "{} - {} ({})",
+                session.started, session.updated, session.username
+              ))
+              .color(Color32::WHITE)
+              .size(self.font_size),
+            );
+            for line in get_session_lines(session) {
+              ui.label(RichText::new(line).color(Color32::GRAY).size(self.font_size));
+            }
+            ui.separator();
+          }
+        });
+      });
   }
 
-  let mut tag = app.small_text("Tag: None", Color32::GRAY);
-
-  if let Some(bedwars_level) = player.bedwars_level {
-    let bedwars_level = bedwars_level as f64;
-    if let Some(final_kills_bedwars) = player.final_kills_bedwars {
-      let final_kills_bedwars = final_kills_bedwars as f64;
-      if let Some(final_deaths_bedwars) = player.final_deaths_bedwars {
-        let final_deaths_bedwars = final_deaths_bedwars as f64;
-        if (bedwars_level < 15. && final_kills_bedwars / final_deaths_bedwars > 5.)
-          || (bedwars_level > 15.
-            && bedwars_level < 100.
-            && bedwars_level / (final_kills_bedwars / final_deaths_bedwars) <= 5.)
-        {
-          tag = app.small_text("Tag: ALT", Color32::YELLOW);
-        } else if let Some(losses_bedwars) = player.losses_bedwars {
-          let losses_bedwars = losses_bedwars as f64;
-          if bedwars_level < 150.
-            && final_deaths_bedwars / losses_bedwars < 0.75
-            && final_kills_bedwars / final_deaths_bedwars < 1.5
+  fn show_history_window(&mut self, ctx: &egui::Context, history: &MatchHistory) {
+    let font_size = self.font_size;
+    let text = |text: &str, color: Color32| RichText::new(text).color(color).size(font_size);
+
+    egui::Window::new("Match history")
+      .open(&mut self.show_history)
+      .show(ctx, |ui| {
+        ui.horizontal(|ui| {
+          if ui
+            .button("Export to JSON")
+            .on_hover_text("Saves all the games to a json file in the current directory")
+            .clicked()
           {
-            tag = app.small_text("Tag: SNIPER", Color32::LIGHT_RED);
+            let file_name = format!("history_export_{}.json", chrono::Local::now().format("%Y%m%d_%H%M%S"));
+            self.history_export_status = Some(match history.export(&file_name) {
+              Ok(()) => format!("Exported to {}", file_name),
+              Err(e) => format!("Export failed: {}", e),
+            });
           }
-        }
+          if let Some(status) = &self.history_export_status {
+            ui.label(status);
+          }
+        });
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+          for (index, record) in history.matches.iter().enumerate().rev() {
+            let (result_text, result_color) = match record.result {
+              Some(GameResult::Win) => ("Win", Color32::LIGHT_GREEN),
+              Some(GameResult::Loss) => ("Loss", Color32::LIGHT_RED),
+              None => ("Unknown", Color32::GRAY),
+            };
+            let header = format!(
+              "{} - {} - {} on {}",
+              record.started,
+              result_text,
+              record.location.mode.as_deref().unwrap_or("Unknown mode"),
+              record.location.map.as_deref().unwrap_or("unknown map")
+            );
+
+            egui::CollapsingHeader::new(text(&header, result_color))
+              .id_source(index)
+              .show(ui, |ui| {
+                if let Some(killer) = &record.killed_by {
+                  ui.label(text(&format!("Final killed by {}", killer), Color32::LIGHT_RED));
+                }
+
+                for entry in record.roster.iter().sorted_by_key(|e| e.team) {
+                  let color = entry.team.map_or(Color32::GRAY, get_team_color);
+                  ui.label(text(
+                    &format!(
+                      "{}{} ⭐{} - FKDR {:.2} - {} finals, {} beds",
+                      entry.team.map_or("".to_string(), |t| format!("[{}] ", t.name())),
+                      entry.stats.username,
+                      entry.stats.bedwars_level.map_or("N/A".to_string(), |x| x.to_string()),
+                      entry.stats.final_ratio,
+                      entry.final_kills,
+                      entry.beds_broken
+                    ),
+                    color,
+                  ));
+                }
+              });
+          }
+        });
+      });
+  }
+
+  fn show_lists_window(
+    &mut self,
+    ctx: &egui::Context,
+    lists: &mut PlayerLists,
+    statuses: &mut HashMap<String, StatusCheck>,
+  ) {
+    let mut uuid_to_remove: Option<String> = None;
+    let mut status_to_check: Option<String> = None;
+
+    egui::Window::new("Blacklist / whitelist")
+      .open(&mut self.show_lists)
+      .show(ctx, |ui| {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+          for (uuid, entry) in lists.entries() {
+            ui.horizontal(|ui| {
+              if ui.button("Remove").clicked() {
+                uuid_to_remove = Some(uuid.clone());
+              }
+              if ui.button("Status").on_hover_text(STATUS_HOVER_TEXT).clicked() {
+                status_to_check = Some(uuid.clone());
+              }
+
+              let color = if entry.reason.is_blacklist() {
+                Color32::LIGHT_RED
+              } else {
+                Color32::LIGHT_GREEN
+              };
+              ui.label(
+                RichText::new(format!("{} - {} ({})", entry.username, entry.reason.name(), entry.date))
+                  .color(color)
+                  .size(self.font_size),
+              );
+            });
+            if !entry.note.is_empty() {
+              ui.label(RichText::new(&entry.note).color(Color32::GRAY).size(self.font_size));
+            }
+            if let Some(status) = statuses.get(uuid) {
+              ui.label(
+                RichText::new(status.description())
+                  .color(Color32::LIGHT_BLUE)
+                  .size(self.font_size),
+              );
+            }
+          }
+        });
+      });
+
+    if let Some(uuid) = uuid_to_remove {
+      lists.remove(&uuid);
+    }
+
+    if let Some(uuid) = status_to_check {
+      statuses.insert(uuid.clone(), StatusCheck::Checking);
+      self.lookups.lookup_status(&uuid);
+    }
+  }
+}
+
+fn get_session_lines(session: &Session) -> Vec<String> {
+  let delta = session.delta();
+
+  vec![
+    format!("Games: {} ({}W/{}L)", delta.games_played, delta.wins, delta.losses),
+    format!("Final kills/deaths: {}/{}", delta.final_kills, delta.final_deaths),
+    format!("Beds broken: {}", delta.beds_broken),
+    format!(
+      "FKDR: {:.2}, WLR: {:.2}",
+      session::ratio(delta.final_kills, delta.final_deaths),
+      session::ratio(delta.wins, delta.losses)
+    ),
+    format!("Stars gained: {}", delta.bedwars_level),
+  ]
+}
+
+fn show_lobby_summary(ui: &mut egui::Ui, summary: &LobbySummary, app: &App) {
+  ui.add_space(5.);
+  ui.horizontal_wrapped(|ui| {
+    ui.label(app.small_text(
+      &format!(
+        "Players: {} (nicked: {})",
+        summary.player_count - summary.nick_count,
+        summary.nick_count
+      ),
+      Color32::WHITE,
+    ));
+    ui.separator();
+    ui.label(app.small_text(
+      &format!("Star: {:.0} avg, {} max", summary.average_star, summary.max_star),
+      Color32::WHITE,
+    ));
+    ui.separator();
+    ui.label(app.small_text(
+      &format!("Final kills/deaths: {:.2} avg", summary.average_final_ratio),
+      Color32::WHITE,
+    ));
+    ui.separator();
+
+    let tagged_color = if summary.tagged_count > 0 {
+      Color32::YELLOW
+    } else {
+      Color32::WHITE
+    };
+    ui.label(app.small_text(&format!("Tagged: {}", summary.tagged_count), tagged_color));
+    ui.separator();
+
+    if let Some((username, threat_score)) = &summary.most_dangerous {
+      ui.label(app.small_text(
+        &format!("Most dangerous: {} ({:.0})", username, threat_score),
+        Color32::LIGHT_RED,
+      ));
+      ui.separator();
+    }
+
+    if let Some((team, threat_score)) = &summary.most_dangerous_team {
+      ui.label(app.small_text(
+        &format!("Most dangerous team: {} ({:.0})", team.name(), threat_score),
+        get_team_color(*team),
+      ));
+      ui.separator();
+    }
+
+    let strength_text = format!("Lobby threat score: {:.0} avg", summary.average_threat_score);
+    match summary.strength_ratio() {
+      Some(ratio) => {
+        let color = if ratio > 2. {
+          Color32::LIGHT_RED
+        } else if ratio > 1. {
+          Color32::YELLOW
+        } else {
+          Color32::LIGHT_GREEN
+        };
+        ui.label(app.small_text(&format!("{} ({:.1}x yours)", strength_text, ratio), color));
+      }
+      None => {
+        ui.label(app.small_text(&strength_text, Color32::WHITE))
+          .on_hover_text("Your username couldn't be detected from the log yet. It can also be set in settings.toml");
+      }
+    }
+  });
+  ui.add_space(5.);
+}
+
+fn show_window_content(ui: &mut egui::Ui, player: &PlayerStats, app: &App) {
+  match &player.status {
+    LookupStatus::Found => {}
+    LookupStatus::Nicked => {
+      ui.label(app.big_text("NICKED", Color32::from_rgb(255, 85, 255)));
+      ui.label(app.small_text("Not a real username", Color32::WHITE));
+      if let Some(chat_rank) = &player.chat_rank {
+        ui.label(app.small_text(&format!("Rank in chat: [{}]", chat_rank), Color32::GRAY));
       }
+      return;
+    }
+    LookupStatus::NeverJoined => {
+      ui.label(app.small_text("Never logged in to Hypixel", Color32::WHITE));
+      return;
+    }
+    LookupStatus::Failed(e) => {
+      ui.label(app.small_text(&format!("Couldn't load stats: {}", e), Color32::WHITE));
+      return;
     }
   }
 
+  let tag = match player.tag() {
+    Some(Tag::Alt) => app.small_text("Tag: ALT", Color32::YELLOW),
+    Some(Tag::Sniper) => app.small_text("Tag: SNIPER", Color32::LIGHT_RED),
+    None => app.small_text("Tag: None", Color32::GRAY),
+  };
+
   ui.label(tag);
   ui.label(app.small_text(
     &format!(
@@ -335,6 +1016,7 @@ fn show_window_content(ui: &mut egui::Ui, player: &PlayerStats, app: &App) {
     Color32::WHITE,
   ));
   ui.label(app.small_text(&format!("Wins/losses: {:.2}", player.win_ratio), Color32::WHITE));
+  ui.label(app.small_text(&format!("Threat score: {:.0}", player.threat_score()), Color32::WHITE));
 
   ui.add_space(15.);
 
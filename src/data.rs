@@ -1,15 +1,29 @@
 use crate::fetching;
-use std::sync::{Arc, Mutex};
+use crate::game::Team;
+use itertools::Itertools;
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LookupStatus {
+  #[default]
+  Found,
+  Nicked,      // mojang doesn't know the username (or the log said it's a nick)
+  NeverJoined, // a real account, but it has never logged in to hypixel
+  Failed(String),
+}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)] // saved in the history, so stats added later shouldn't make it unreadable
 pub struct PlayerStats {
   pub username: String,
+  pub uuid: Option<String>,
 
-  pub no_data: bool, // if true, it probably means that the player is nicked
+  pub status: LookupStatus,
 
   pub rank: Option<String>,
   pub donator_rank: Option<String>,
   pub monthly_rank: Option<String>, // if its "SUPERSTAR", its probably mvp++
+  pub chat_rank: Option<String>,    // the rank shown in the log, which is all we know about nicked players
 
   pub achievement_points: Option<i64>,
 
@@ -32,36 +46,125 @@ pub struct PlayerStats {
 
   pub guild_name: Option<String>,
 
+  #[serde(deserialize_with = "deserialize_ratio")]
   pub beds_ratio: f64,
+  #[serde(deserialize_with = "deserialize_ratio")]
   pub final_ratio: f64,
+  #[serde(deserialize_with = "deserialize_ratio")]
   pub win_ratio: f64,
 }
 
+/// The ratios of players without deaths (or losses) aren't finite, which json saves as null
+fn deserialize_ratio<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+  Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tag {
+  Alt,
+  Sniper,
+}
+
+impl PlayerStats {
+  pub fn has_data(&self) -> bool {
+    self.status == LookupStatus::Found
+  }
+
+  pub fn nicked(username: &str) -> Self {
+    PlayerStats {
+      username: username.to_string(),
+      status: LookupStatus::Nicked,
+      ..Default::default()
+    }
+  }
+
+  pub fn tag(&self) -> Option<Tag> {
+    let bedwars_level = self.bedwars_level? as f64;
+    let final_kills_bedwars = self.final_kills_bedwars? as f64;
+    let final_deaths_bedwars = self.final_deaths_bedwars? as f64;
+
+    if (bedwars_level < 15. && final_kills_bedwars / final_deaths_bedwars > 5.)
+      || (bedwars_level > 15.
+        && bedwars_level < 100.
+        && bedwars_level / (final_kills_bedwars / final_deaths_bedwars) <= 5.)
+    {
+      return Some(Tag::Alt);
+    }
+
+    let losses_bedwars = self.losses_bedwars? as f64;
+    if bedwars_level < 150.
+      && final_deaths_bedwars / losses_bedwars < 0.75
+      && final_kills_bedwars / final_deaths_bedwars < 1.5
+    {
+      return Some(Tag::Sniper);
+    }
+
+    None
+  }
+
+  /// Bedwars "index": star * fkdr^2. Players without data (e.g. nicked) have a score of 0
+  pub fn threat_score(&self) -> f64 {
+    if !self.has_data() {
+      return 0.;
+    }
+
+    // with 0 final deaths the ratio is infinite (or NaN), so use the final kills instead
+    let final_ratio = if self.final_ratio.is_finite() {
+      self.final_ratio
+    } else {
+      self.final_kills_bedwars.unwrap_or(0) as f64
+    };
+
+    self.bedwars_level.unwrap_or(0) as f64 * final_ratio.powi(2)
+  }
+}
+
 pub fn get_stats(username: &str) -> PlayerStats {
-  let game_stats_response = fetching::get_game_stats(username.to_string());
+  let failed = |e: String| {
+    dbg!(&e);
+
+    PlayerStats {
+      username: username.to_string(),
+      status: LookupStatus::Failed(e),
+      ..Default::default()
+    }
+  };
 
-  if game_stats_response.is_err() {
-    dbg!(game_stats_response.unwrap_err());
+  let uuid = match fetching::get_uuid(username.to_string()) {
+    Ok(Some(uuid)) => uuid,
+    Ok(None) => return PlayerStats::nicked(username),
+    Err(e) => return failed(e),
+  };
+
+  let game_stats = match fetching::get_game_stats(uuid.clone()) {
+    Ok(game_stats) => game_stats,
+    Err(e) => return failed(e),
+  };
+
+  let player = &game_stats["player"];
 
+  if player.is_null() {
     return PlayerStats {
       username: username.to_string(),
-      no_data: true,
+      uuid: Some(uuid),
+      status: LookupStatus::NeverJoined,
       ..Default::default()
     };
   }
 
-  let guild_response = fetching::get_guild(username.to_string());
+  let guild_response = fetching::get_guild(uuid.clone());
 
-  let player = &game_stats_response.unwrap()["player"];
   let guild = guild_response.map(|g| g["guild"].clone()).ok();
 
   let mut player = PlayerStats {
     username: username.to_string(),
-    no_data: false,
+    uuid: Some(uuid),
+    status: LookupStatus::Found,
 
     rank: player["rank"].as_str().map(|x| x.to_string()),
     donator_rank: player["newPackageRank"].as_str().map(|x| x.to_string()),
     monthly_rank: player["monthlyPackageRank"].as_str().map(|x| x.to_string()),
+    chat_rank: None,
 
     achievement_points: player["achievementPoints"].as_i64(),
 
@@ -110,10 +213,161 @@ pub fn get_stats(username: &str) -> PlayerStats {
   player
 }
 
-pub fn sort_players(data_arc: Arc<Mutex<crate::app::AppData>>) {
-  let mut data = data_arc.lock().unwrap();
+/// Whether a player is online and what they're playing, from /v2/status
+#[derive(Debug, Clone, Default)]
+pub struct OnlineStatus {
+  pub online: bool,
+  pub game_type: Option<String>,
+  pub mode: Option<String>,
+  pub map: Option<String>,
+  pub checked: String, // the time it was checked
+}
+
+#[derive(Debug, Clone)]
+pub enum StatusCheck {
+  Checking,
+  Checked(OnlineStatus),
+  Failed(String),
+}
+
+impl StatusCheck {
+  pub fn description(&self) -> String {
+    match self {
+      StatusCheck::Checking => "Checking status...".to_string(),
+      StatusCheck::Checked(status) if !status.online => format!("Offline (checked {})", status.checked),
+      StatusCheck::Checked(status) => {
+        let game = [&status.game_type, &status.mode].into_iter().flatten().join(" ");
+        let map = status.map.as_ref().map_or(String::new(), |map| format!(" on {}", map));
+
+        format!("Online: {}{} (checked {})", game, map, status.checked)
+      }
+      StatusCheck::Failed(e) => format!("Couldn't check status: {}", e),
+    }
+  }
+}
+
+pub fn get_online_status(uuid: &str) -> StatusCheck {
+  let response = match fetching::get_status(uuid.to_string()) {
+    Ok(response) => response,
+    Err(e) => return StatusCheck::Failed(e),
+  };
+
+  let session = &response["session"];
+
+  StatusCheck::Checked(OnlineStatus {
+    online: session["online"].as_bool().unwrap_or(false),
+    game_type: session["gameType"].as_str().map(|x| x.to_string()),
+    mode: session["mode"].as_str().map(|x| x.to_string()),
+    map: session["map"].as_str().map(|x| x.to_string()),
+    checked: chrono::Local::now().format("%H:%M").to_string(),
+  })
+}
+
+#[derive(Debug, Default)]
+pub struct LobbySummary {
+  pub player_count: usize,
+  pub nick_count: usize,
+
+  pub average_star: f64,
+  pub max_star: i64,
+  pub average_final_ratio: f64,
+
+  pub tagged_count: usize,
+  pub most_dangerous: Option<(String, f64)>,    // username and threat score
+  pub most_dangerous_team: Option<(Team, f64)>, // enemy team and its total threat score
+
+  pub average_threat_score: f64,
+  pub own_threat_score: Option<f64>,
+}
+
+impl LobbySummary {
+  /// How many times stronger the lobby is than us, if our own stats are known
+  pub fn strength_ratio(&self) -> Option<f64> {
+    self
+      .own_threat_score
+      .filter(|own| *own > 0.)
+      .map(|own| self.average_threat_score / own)
+  }
+}
+
+/// The own player and party members are left out of the averages, the own stats are only used for the comparison
+pub fn summarize_lobby(data: &crate::app::AppData) -> LobbySummary {
+  let players = &data.players;
+  let others = players
+    .iter()
+    .filter(|p| !data.is_own(&p.username) && !data.is_in_party(&p.username))
+    .collect_vec();
+  let with_data = others.iter().filter(|p| p.has_data()).collect_vec();
+
+  let own_team = data.own_username.as_ref().and_then(|own| data.match_state.team_of(own));
+
+  let average = |values: Vec<f64>| {
+    if values.is_empty() {
+      0.
+    } else {
+      values.iter().sum::<f64>() / values.len() as f64
+    }
+  };
+
+  LobbySummary {
+    player_count: players.len(),
+    nick_count: players.iter().filter(|p| p.status == LookupStatus::Nicked).count(),
+
+    average_star: average(
+      with_data
+        .iter()
+        .filter_map(|p| p.bedwars_level)
+        .map(|x| x as f64)
+        .collect(),
+    ),
+    max_star: with_data.iter().filter_map(|p| p.bedwars_level).max().unwrap_or(0),
+    average_final_ratio: average(
+      with_data
+        .iter()
+        .map(|p| p.final_ratio)
+        .filter(|x| x.is_finite())
+        .collect(),
+    ),
+
+    tagged_count: with_data.iter().filter(|p| p.tag().is_some()).count(),
+    most_dangerous: with_data
+      .iter()
+      .max_by(|p1, p2| p1.threat_score().total_cmp(&p2.threat_score()))
+      .map(|p| (p.username.clone(), p.threat_score())),
+
+    most_dangerous_team: with_data
+      .iter()
+      .filter_map(|p| Some((data.match_state.team_of(&p.username)?, p.threat_score())))
+      .filter(|(team, _)| own_team != Some(*team))
+      .into_grouping_map()
+      .sum()
+      .into_iter()
+      .max_by(|(_, t1), (_, t2)| t1.total_cmp(t2)),
+
+    average_threat_score: average(with_data.iter().map(|p| p.threat_score()).collect()),
+    own_threat_score: data
+      .own_stats
+      .as_ref()
+      .filter(|p| p.has_data())
+      .map(|p| p.threat_score()),
+  }
+}
 
-  data
-    .players
-    .sort_by(|p1, p2| p2.final_ratio.partial_cmp(&p1.final_ratio).unwrap());
+pub fn sort_players(data: &mut crate::app::AppData) {
+  // the own player and party members are always pinned first
+  let mut players = std::mem::take(&mut data.players);
+  // during a game, the rest are grouped by team
+  players.sort_by(|p1, p2| {
+    data
+      .player_group(&p1.username)
+      .cmp(&data.player_group(&p2.username))
+      .then(
+        data
+          .match_state
+          .team_of(&p1.username)
+          .cmp(&data.match_state.team_of(&p2.username)),
+      )
+      .then(p2.final_ratio.total_cmp(&p1.final_ratio))
+  });
+  data.players = players;
 }
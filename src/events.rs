@@ -0,0 +1,462 @@
+use crate::alerts;
+use crate::app::AppData;
+use crate::data;
+use crate::data::PlayerStats;
+use crate::fetching;
+use crate::game::{MatchState, Team};
+use crate::history::MatchRecord;
+use crate::lookup::{LookupPurpose, LookupService, PlayerLoaded, StatusLoaded};
+use crate::parsing::{LobbyEvent, LogEvent, ParsedLine};
+use std::time::{Duration, Instant};
+
+// a bit longer than the time the player stats are cached for
+const OWN_STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(200);
+
+/// Applies what the log parser sent. Called by the ui every frame
+pub fn handle_lobby_event(data: &mut AppData, event: LobbyEvent, lookups: &LookupService) {
+  match event {
+    LobbyEvent::Log(event) => {
+      let paused = data.settings.paused || !data.pending_events.is_empty();
+      let is_lobby_change = matches!(
+        event.parsed_line,
+        ParsedLine::JoinedLobby { .. }
+          | ParsedLine::LeftLobby { .. }
+          | ParsedLine::LobbyList { .. }
+          | ParsedLine::Nick { .. }
+      );
+
+      if !paused {
+        handle_log_event(data, event, lookups);
+      } else if !event.backlog {
+        // while paused (or until the user decides what to do with the queued events) the events are only queued
+        data.pending_events.push(event);
+      } else if !is_lobby_change {
+        // the lines from before jahbo was started didn't arrive while paused. The old lobbies are skipped, but the
+        // rest (e.g. the own username and the party) is still true
+        handle_log_event(data, event, lookups);
+      }
+    }
+    LobbyEvent::Heartbeat => {
+      data.parser_heartbeat = Some(Instant::now());
+      data.parser_crash = None;
+    }
+    LobbyEvent::ParserCrashed(error) => data.parser_crash = Some(error),
+  }
+}
+
+/// Applies the events which were queued while paused, in the order they were read
+pub fn apply_pending_events(data: &mut AppData, lookups: &LookupService) {
+  for event in std::mem::take(&mut data.pending_events) {
+    handle_log_event(data, event, lookups);
+  }
+}
+
+fn handle_log_event(data: &mut AppData, event: LogEvent, lookups: &LookupService) {
+  let LogEvent {
+    parsed_line,
+    source,
+    time,
+    backlog,
+  } = event;
+  data.last_event_source = Some(source);
+
+  match parsed_line {
+    ParsedLine::JoinedLobby { username, rank } => {
+      if data.settings.auto_join_active && !data.has_player(&username) {
+        data.lobby_lookups.insert(username.to_lowercase());
+        lookups.lookup(&username, rank, LookupPurpose::Join);
+      }
+    }
+    ParsedLine::LeftLobby { username } => {
+      if !data.settings.auto_leave_active {
+        return;
+      }
+
+      // they could leave before their stats are loaded
+      data.lobby_lookups.remove(&username.to_lowercase());
+
+      if let Some(index) = data.players.iter().position(|s| s.username == username) {
+        data.players.remove(index);
+        println!("Removed {}", username);
+
+        if data.settings.auto_sort {
+          data::sort_players(data);
+        }
+      }
+    }
+    ParsedLine::LobbyList { usernames } => {
+      if data.settings.auto_clear_on_who {
+        data.clear_players();
+      }
+
+      if !data.settings.auto_add_on_who {
+        return;
+      }
+
+      // don't add players which are already added
+      let usernames: Vec<String> = usernames.into_iter().filter(|u| !data.has_player(u)).collect();
+      data.lobby_lookups.extend(usernames.iter().map(|u| u.to_lowercase()));
+      lookups.lookup_lobby_list(usernames);
+    }
+    ParsedLine::GameStart => {
+      println!("Game has started");
+      // used once, so a game where /locraw wasn't run doesn't get the location of the last one
+      let location = std::mem::take(&mut data.location);
+      data.match_state = MatchState::started(time, location, data.players.clone());
+    }
+    ParsedLine::GameEnd { result } => {
+      if data.match_state.in_progress {
+        let result = result.or_else(|| {
+          let own_username = data.own_username.as_ref()?;
+          data.match_state.infer_result(own_username)
+        });
+
+        println!("Game has ended ({:?})", result);
+        data.match_state.result = result;
+
+        // the games from before jahbo was started were already recorded back then
+        if !backlog {
+          if let Some(result) = result {
+            data.session.add_result(result);
+          }
+
+          data
+            .history
+            .add(MatchRecord::new(&data.match_state, data.own_username.as_deref(), time));
+        }
+
+        // the teams and counters of this game shouldn't carry over to the next lobby
+        data.match_state = MatchState::ended(result);
+
+        if data.settings.auto_clear_on_game_end {
+          data.clear_players();
+        } else if data.settings.auto_sort {
+          data::sort_players(data);
+        }
+      } else if data.match_state.ended && data.match_state.result.is_none() {
+        // the result can come after the summary
+        if let Some(result) = result {
+          println!("Game result is {:?}", result);
+          data.match_state.result = Some(result);
+          if !backlog {
+            data.session.add_result(result);
+            data.history.set_last_result(result);
+          }
+        }
+      }
+    }
+    ParsedLine::FinalKill { victim, killer } => {
+      println!(
+        "{} was final killed by {}",
+        victim,
+        killer.as_deref().unwrap_or("nobody")
+      );
+      data.match_state.add_final_kill(&victim, killer.as_deref());
+    }
+    ParsedLine::BedDestroyed { team, breaker } => {
+      // "Your Bed" is the bed of our own team
+      let team = team.or_else(|| {
+        let own_username = data.own_username.as_ref()?;
+        data.match_state.team_of(own_username)
+      });
+
+      println!("{} bed was destroyed by {}", team.map_or("Own", |t| t.name()), breaker);
+      data.match_state.add_bed_destroyed(team, &breaker);
+    }
+    ParsedLine::TeamEliminated { team } => {
+      println!("{} team was eliminated", team.name());
+      data.match_state.eliminated_teams.insert(team);
+    }
+    ParsedLine::Location { location } => {
+      // only lobbies and games have a mode
+      if location.mode.is_some() {
+        println!("Playing {:?} on {:?}", location.mode, location.map);
+        data.location = location;
+      }
+    }
+    ParsedLine::TeamMembers { members } => add_team_members(data, members),
+    ParsedLine::Nick { username } => {
+      if let Some(player) = data
+        .players
+        .iter_mut()
+        .find(|p| p.username.to_lowercase() == username.to_lowercase())
+      {
+        // a nick can be the name of a real account, so trust the log over the lookup. The stats and uuid (and so the
+        // list entry) were of that account, not of this player
+        let chat_rank = player.chat_rank.take();
+        *player = PlayerStats::nicked(&username);
+        player.chat_rank = chat_rank;
+      } else if data.settings.auto_join_active {
+        let player = PlayerStats::nicked(&username);
+
+        alerts::alert_on_join(data, &player);
+        data.add_player(player);
+      }
+    }
+    ParsedLine::SettingUser { username } => {
+      if crate::app::get_username_override().is_none() {
+        println!("Detected own username {}", username);
+        load_own_stats(data, &username, lookups);
+      }
+    }
+    ParsedLine::PartyList { count } => {
+      // the members are listed in the next lines
+      println!("Party has {} members", count);
+      data.party.clear();
+    }
+    ParsedLine::PartyMembers { usernames } => {
+      for username in usernames {
+        if !data.is_own(&username) && !data.is_in_party(&username) {
+          println!("{} is in the party", username);
+          data.party.push(username);
+        }
+      }
+
+      if data.settings.auto_sort {
+        data::sort_players(data);
+      }
+    }
+    ParsedLine::PartyMemberLeft { username } => {
+      data.party.retain(|p| p.to_lowercase() != username.to_lowercase());
+
+      if data.settings.auto_sort {
+        data::sort_players(data);
+      }
+    }
+    ParsedLine::PartyDisbanded => {
+      println!("Left the party");
+      data.party.clear();
+    }
+    ParsedLine::Nothing => {}
+  }
+}
+
+fn add_team_members(data: &mut AppData, members: Vec<(String, Team)>) {
+  for (username, team) in members {
+    if data.match_state.team_of(&username) != Some(team) {
+      println!("{} is in the {} team", username, team.name());
+      data.match_state.set_team(&username, team);
+    }
+  }
+
+  if data.settings.auto_sort {
+    data::sort_players(data);
+  }
+}
+
+/// Applies the stats the lookup service loaded. Called by the ui every frame
+pub fn handle_player_loaded(data: &mut AppData, loaded: PlayerLoaded) {
+  let PlayerLoaded {
+    player,
+    purpose,
+    username,
+  } = loaded;
+
+  match purpose {
+    LookupPurpose::Join | LookupPurpose::LobbyList | LookupPurpose::Manual => {
+      // lobby players who left (or were cleared) while their stats were loading aren't added
+      let in_lobby = data.lobby_lookups.remove(&username.to_lowercase());
+      if purpose != LookupPurpose::Manual && !in_lobby {
+        return;
+      }
+
+      // the player could have been added while the stats were loading (maybe under their old name)
+      if data.has_player(&player.username)
+        || (player.uuid.is_some() && data.players.iter().any(|p| p.uuid == player.uuid))
+      {
+        return;
+      }
+
+      if purpose != LookupPurpose::Manual {
+        alerts::alert_on_join(data, &player);
+      }
+      data.add_player(player);
+
+      if data.settings.auto_sort {
+        data::sort_players(data);
+      }
+    }
+    LookupPurpose::OwnStats => {
+      if data.is_own(&player.username) {
+        data.session.update(&player);
+        data.own_stats = Some(player);
+      }
+    }
+    LookupPurpose::NewSession => {
+      data.session.start(&player);
+      data.own_stats = Some(player);
+    }
+  }
+}
+
+pub fn handle_status_loaded(data: &mut AppData, loaded: StatusLoaded) {
+  data.statuses.insert(loaded.uuid, loaded.status);
+}
+
+/// Sets the own username and loads the own stats, which the lobby is compared against
+pub fn load_own_stats(data: &mut AppData, username: &str, lookups: &LookupService) {
+  data.own_username = Some(username.to_string());
+  data.own_stats_requested = Some(Instant::now());
+  lookups.lookup(username, None, LookupPurpose::OwnStats);
+}
+
+/// Refetches the own stats every few minutes, so the session stays up to date
+pub fn refresh_own_stats(data: &mut AppData, lookups: &LookupService) {
+  let Some(own_username) = data.own_username.clone() else {
+    return;
+  };
+
+  if data
+    .own_stats_requested
+    .is_none_or(|requested| requested.elapsed() >= OWN_STATS_REFRESH_INTERVAL)
+  {
+    load_own_stats(data, &own_username, lookups);
+  }
+}
+
+/// Ends the current session and starts a new one from freshly fetched stats
+pub fn start_session(data: &AppData, lookups: &LookupService) {
+  if let Some(own_username) = &data.own_username {
+    // the cached stats can be a few minutes old, which would count the last games in the old session and the new one
+    if let Some(uuid) = data.own_stats.as_ref().and_then(|s| s.uuid.as_deref()) {
+      fetching::forget_game_stats(uuid);
+    }
+    lookups.lookup(own_username, None, LookupPurpose::NewSession);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::game::GameResult;
+
+  fn handle(data: &mut AppData, parsed_line: ParsedLine, backlog: bool) {
+    let (lookups, _, _) = LookupService::start();
+    let event = LogEvent {
+      parsed_line,
+      source: "test.log".to_string(),
+      time: "2024-01-01 12:00".to_string(),
+      backlog,
+    };
+    handle_log_event(data, event, &lookups);
+  }
+
+  fn in_game(backlog: bool) -> AppData {
+    let mut data = AppData {
+      own_username: Some("Me".to_string()),
+      ..Default::default()
+    };
+    handle(&mut data, ParsedLine::GameStart, backlog);
+    handle(
+      &mut data,
+      ParsedLine::TeamMembers {
+        members: vec![("Me".to_string(), Team::Red), ("Foo".to_string(), Team::Blue)],
+      },
+      backlog,
+    );
+    data
+  }
+
+  #[test]
+  fn game_is_recorded_once_and_reset() {
+    let mut data = in_game(false);
+    handle(
+      &mut data,
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Loss),
+      },
+      false,
+    );
+    handle(
+      &mut data,
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Win),
+      },
+      false,
+    );
+
+    assert_eq!(data.history.matches.len(), 1);
+    assert_eq!(data.history.matches[0].result, Some(GameResult::Loss));
+    assert_eq!(data.history.matches[0].started, "2024-01-01 12:00");
+    assert_eq!((data.session.wins, data.session.losses), (0, 1));
+    assert!(!data.match_state.in_progress);
+    assert_eq!(data.match_state.team_of("Me"), None);
+  }
+
+  #[test]
+  fn result_is_inferred_from_the_final_death() {
+    let mut data = in_game(false);
+    handle(
+      &mut data,
+      ParsedLine::FinalKill {
+        victim: "Me".to_string(),
+        killer: Some("Foo".to_string()),
+      },
+      false,
+    );
+    handle(&mut data, ParsedLine::GameEnd { result: None }, false);
+
+    let record = &data.history.matches[0];
+    assert_eq!(record.result, Some(GameResult::Loss));
+    assert_eq!(record.killed_by.as_deref(), Some("Foo"));
+  }
+
+  #[test]
+  fn result_after_the_summary() {
+    // without an own team the summary doesn't say if we won, the "+N Bed Wars Experience (Win)" after it does
+    let mut data = AppData {
+      own_username: Some("Me".to_string()),
+      ..Default::default()
+    };
+    handle(&mut data, ParsedLine::GameStart, false);
+    handle(&mut data, ParsedLine::GameEnd { result: None }, false);
+    assert_eq!(data.history.matches[0].result, None);
+
+    for _ in 0..2 {
+      handle(
+        &mut data,
+        ParsedLine::GameEnd {
+          result: Some(GameResult::Win),
+        },
+        false,
+      );
+    }
+
+    assert_eq!(data.history.matches.len(), 1);
+    assert_eq!(data.history.matches[0].result, Some(GameResult::Win));
+    assert_eq!((data.session.wins, data.session.losses), (1, 0));
+  }
+
+  #[test]
+  fn backlog_games_are_not_recorded() {
+    let mut data = in_game(true);
+    handle(&mut data, ParsedLine::GameEnd { result: None }, true);
+    handle(
+      &mut data,
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Win),
+      },
+      true,
+    );
+
+    assert!(data.history.matches.is_empty());
+    assert_eq!((data.session.wins, data.session.losses), (0, 0));
+    assert!(data.match_state.ended);
+    assert_eq!(data.match_state.team_of("Me"), None);
+  }
+
+  #[test]
+  fn game_end_without_a_game_does_nothing() {
+    let mut data = AppData::default();
+    handle(
+      &mut data,
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Win),
+      },
+      false,
+    );
+
+    assert!(data.history.matches.is_empty());
+    assert_eq!(data.session.wins, 0);
+  }
+}
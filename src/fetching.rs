@@ -1,56 +1,202 @@
+use crate::keys;
 use cached::proc_macro::cached;
+use cached::Cached;
+use itertools::Itertools;
+use reqwest::StatusCode;
+use std::thread;
+use std::time::Duration;
 
-#[cached]
-pub fn get_uuid(username: String) -> Result<String, String> {
-  let response = reqwest::blocking::get(format!("https://api.mojang.com/users/profiles/minecraft/{}", username))
+// the most usernames mojang resolves in one request
+const BULK_UUID_LIMIT: usize = 10;
+
+// the urls can be changed in settings.toml, e.g. to use a caching proxy or a local mock server
+const DEFAULT_UUID_URL: &str = "https://api.mojang.com/users/profiles/minecraft/{username}";
+const DEFAULT_UUID_FIELD: &str = "/id";
+const DEFAULT_UUID_BULK_URL: &str = "https://api.minecraftservices.com/minecraft/profile/lookup/bulk/byname";
+const DEFAULT_HYPIXEL_URL: &str = "https://api.hypixel.net";
+// a small endpoint which needs an api key, to check if the keys work
+const KEY_VALIDATION_PATH: &str = "/v2/punishmentstats";
+const KEY_VALIDATION_INTERVAL: Duration = Duration::from_secs(600);
+
+/// A proxy (like `jahbo serve`) has its own keys
+pub fn uses_custom_hypixel_url() -> bool {
+  crate::get_string_setting("hypixel_url", DEFAULT_HYPIXEL_URL) != DEFAULT_HYPIXEL_URL
+}
+
+fn get_hypixel_url(path: &str) -> String {
+  format!(
+    "{}{}",
+    crate::get_string_setting("hypixel_url", DEFAULT_HYPIXEL_URL).trim_end_matches('/'),
+    path
+  )
+}
+
+/// Returns `Ok(None)` if Mojang doesn't know the username, which means the player is nicked
+#[cached(result = true)]
+pub fn get_uuid(username: String) -> Result<Option<String>, String> {
+  let url = crate::get_string_setting("uuid_url", DEFAULT_UUID_URL).replace("{username}", &username);
+  let response = reqwest::blocking::get(url).map_err(|e| e.to_string())?;
+
+  if matches!(response.status(), StatusCode::NOT_FOUND | StatusCode::NO_CONTENT) {
+    return Ok(None);
+  }
+
+  let response = response
+    .error_for_status()
     .map_err(|e| e.to_string())?
     .json::<serde_json::Value>()
     .map_err(|e| e.to_string())?;
 
-  response["id"]
-    .as_str()
+  // other uuid services put the uuid somewhere else (e.g. "/data/player/raw_id" for PlayerDB)
+  response
+    .pointer(&crate::get_string_setting("uuid_field", DEFAULT_UUID_FIELD))
+    .and_then(|x| x.as_str())
     .ok_or_else(|| "Couldn't get uuid field".to_string())
-    .map(|x| x.to_string())
+    .map(|x| Some(x.to_string()))
 }
 
-#[cached(time = 600, result = true)]
-pub fn get_guild(username: String) -> Result<serde_json::Value, String> {
-  let api_key = crate::get_toml_value("settings.toml", "api_key")
-    .as_str()
+/// The uuid of the player if it was already resolved, without making a request
+pub fn get_cached_uuid(username: &str) -> Option<String> {
+  GET_UUID
+    .lock()
     .unwrap()
-    .to_string();
+    .cache_get(&username.to_string())
+    .cloned()
+    .flatten()
+}
 
-  let uuid = get_uuid(username)?;
+/// Resolves the usernames with Mojang's bulk endpoint, a few per request, and puts them in the `get_uuid` cache.
+/// Usernames which are already cached aren't resolved again. Does nothing if uuid_bulk_url is set to ""
+pub fn get_uuids(usernames: &[String]) -> Result<(), String> {
+  let url = crate::get_string_setting("uuid_bulk_url", DEFAULT_UUID_BULK_URL);
+  if url.is_empty() {
+    return Ok(());
+  }
 
-  reqwest::blocking::Client::new()
-    .get("https://api.hypixel.net/v2/guild")
-    .query(&[("player", uuid)])
-    .header("API-Key", api_key)
-    .send()
-    .map_err(|e| e.to_string())?
-    .error_for_status()
-    .map_err(|e| e.to_string())?
-    .json::<serde_json::Value>()
-    .map_err(|e| e.to_string())
+  let uncached = {
+    let mut cache = GET_UUID.lock().unwrap();
+    usernames
+      .iter()
+      .filter(|username| cache.cache_get(*username).is_none())
+      .cloned()
+      .collect_vec()
+  };
+
+  for chunk in uncached.chunks(BULK_UUID_LIMIT) {
+    let response = reqwest::blocking::Client::new()
+      .post(&url)
+      .json(chunk)
+      .send()
+      .map_err(|e| e.to_string())?
+      .error_for_status()
+      .map_err(|e| e.to_string())?
+      .json::<serde_json::Value>()
+      .map_err(|e| e.to_string())?;
+
+    let profiles = response
+      .as_array()
+      .ok_or_else(|| "Couldn't get the profiles".to_string())?;
+
+    let mut cache = GET_UUID.lock().unwrap();
+    for username in chunk {
+      // usernames mojang doesn't know are left out, so they're nicked
+      let uuid = profiles
+        .iter()
+        .find(|p| {
+          p["name"]
+            .as_str()
+            .is_some_and(|name| name.eq_ignore_ascii_case(username))
+        })
+        .and_then(|p| p["id"].as_str())
+        .map(|x| x.to_string());
+
+      cache.cache_set(username.clone(), uuid);
+    }
+  }
+
+  Ok(())
 }
 
-#[cached(time = 180, result = true)]
-pub fn get_game_stats(username: String) -> Result<serde_json::Value, String> {
-  let api_key = crate::get_toml_value("settings.toml", "api_key")
-    .as_str()
-    .unwrap()
-    .to_string();
+/// Takes turns between the api keys, and tries the next one if a key is invalid or rate limited.
+/// Without an api key (e.g. when hypixel_url is a `jahbo serve` proxy) the requests are sent without one
+fn get_hypixel(path: &str, query: (&str, String)) -> Result<serde_json::Value, String> {
+  let send = |api_key: Option<&str>| {
+    let mut request = reqwest::blocking::Client::new()
+      .get(get_hypixel_url(path))
+      .query(&[&query]);
 
-  let uuid = get_uuid(username)?;
+    if let Some(api_key) = api_key {
+      request = request.header("API-Key", api_key);
+    }
 
-  reqwest::blocking::Client::new()
-    .get("https://api.hypixel.net/v2/player")
-    .query(&[("uuid", uuid)])
-    .header("API-Key", api_key)
-    .send()
-    .map_err(|e| e.to_string())?
+    request.send().map_err(|e| e.to_string())
+  };
+
+  let response = if keys::has_keys() {
+    loop {
+      let api_key = keys::next_key().ok_or_else(|| "All API keys are invalid or rate limited".to_string())?;
+      let response = send(Some(&api_key))?;
+      keys::report_response(&api_key, &response);
+
+      if !matches!(response.status(), StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS) {
+        break response;
+      }
+    }
+  } else {
+    send(None)?
+  };
+
+  response
     .error_for_status()
     .map_err(|e| e.to_string())?
     .json::<serde_json::Value>()
     .map_err(|e| e.to_string())
 }
+
+/// Checks every key with an authenticated request, so a wrong key is noticed before any player is looked up
+pub fn validate_api_keys() {
+  keys::load_keys();
+
+  for api_key in keys::get_keys() {
+    let response = reqwest::blocking::Client::new()
+      .get(get_hypixel_url(KEY_VALIDATION_PATH))
+      .header("API-Key", &api_key.key)
+      .send();
+
+    match response {
+      Ok(response) => keys::report_response(&api_key.key, &response),
+      Err(e) => {
+        dbg!(e);
+      }
+    }
+  }
+}
+
+/// Validates the keys every few minutes, they can expire or be revoked while jahbo is running
+pub fn keep_validating_api_keys() {
+  loop {
+    validate_api_keys();
+    thread::sleep(KEY_VALIDATION_INTERVAL);
+  }
+}
+
+#[cached(time = 600, result = true)]
+pub fn get_guild(uuid: String) -> Result<serde_json::Value, String> {
+  get_hypixel("/v2/guild", ("player", uuid))
+}
+
+/// Players can hide their online status in their api settings, then they always show as offline
+#[cached(time = 30, result = true)]
+pub fn get_status(uuid: String) -> Result<serde_json::Value, String> {
+  get_hypixel("/v2/status", ("uuid", uuid))
+}
+
+#[cached(time = 180, result = true)]
+pub fn get_game_stats(uuid: String) -> Result<serde_json::Value, String> {
+  get_hypixel("/v2/player", ("uuid", uuid))
+}
+
+/// So the next `get_game_stats` of the player fetches the current stats
+pub fn forget_game_stats(uuid: &str) {
+  GET_GAME_STATS.lock().unwrap().cache_remove(&uuid.to_string());
+}
@@ -0,0 +1,233 @@
+use crate::data::PlayerStats;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Team {
+  Red,
+  Blue,
+  Green,
+  Yellow,
+  Aqua,
+  White,
+  Pink,
+  Gray,
+}
+
+impl Team {
+  pub fn name(self) -> &'static str {
+    match self {
+      Team::Red => "Red",
+      Team::Blue => "Blue",
+      Team::Green => "Green",
+      Team::Yellow => "Yellow",
+      Team::Aqua => "Aqua",
+      Team::White => "White",
+      Team::Pink => "Pink",
+      Team::Gray => "Gray",
+    }
+  }
+
+  /// Parses names like "RED" or "Red"
+  pub fn from_name(name: &str) -> Option<Team> {
+    match name.to_uppercase().as_str() {
+      "RED" => Some(Team::Red),
+      "BLUE" => Some(Team::Blue),
+      "GREEN" => Some(Team::Green),
+      "YELLOW" => Some(Team::Yellow),
+      "AQUA" => Some(Team::Aqua),
+      "WHITE" => Some(Team::White),
+      "PINK" => Some(Team::Pink),
+      "GRAY" | "GREY" => Some(Team::Gray),
+      _ => None,
+    }
+  }
+
+  /// The minecraft formatting code (the character after `§`) which hypixel uses for the team
+  pub fn from_color_code(code: char) -> Option<Team> {
+    match code {
+      'c' => Some(Team::Red),
+      '9' => Some(Team::Blue),
+      'a' => Some(Team::Green),
+      'e' => Some(Team::Yellow),
+      'b' => Some(Team::Aqua),
+      'f' => Some(Team::White),
+      'd' => Some(Team::Pink),
+      '8' => Some(Team::Gray),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameResult {
+  Win,
+  Loss,
+}
+
+/// Everything we know about the game that is currently being played. Reset when a game starts and when it ends
+#[derive(Debug, Default)]
+pub struct MatchState {
+  // all keyed by lowercase username
+  teams: HashMap<String, Team>,
+  final_kills: HashMap<String, u32>,
+  beds_broken: HashMap<String, u32>,
+  final_deaths: HashMap<String, Option<String>>, // and who killed them
+
+  pub destroyed_beds: HashSet<Team>,
+  pub eliminated_teams: HashSet<Team>,
+
+  pub in_progress: bool,
+  pub ended: bool,
+  pub result: Option<GameResult>,
+
+  pub started: String,
+  pub location: Location,
+  pub roster: Vec<PlayerStats>, // the lobby as it was when the game started
+}
+
+/// Where we are, from the output of /locraw
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+  pub mode: Option<String>,
+  pub map: Option<String>,
+}
+
+impl MatchState {
+  pub fn started(started: String, location: Location, roster: Vec<PlayerStats>) -> Self {
+    Self {
+      in_progress: true,
+      started,
+      location,
+      roster,
+      ..Default::default()
+    }
+  }
+
+  /// What's kept after a game, so a result which is logged after the summary can still be recorded
+  pub fn ended(result: Option<GameResult>) -> Self {
+    Self {
+      ended: true,
+      result,
+      ..Default::default()
+    }
+  }
+
+  pub fn team_of(&self, username: &str) -> Option<Team> {
+    self.teams.get(&username.to_lowercase()).copied()
+  }
+
+  pub fn set_team(&mut self, username: &str, team: Team) {
+    self.teams.insert(username.to_lowercase(), team);
+  }
+
+  pub fn final_kills_of(&self, username: &str) -> u32 {
+    self.final_kills.get(&username.to_lowercase()).copied().unwrap_or(0)
+  }
+
+  pub fn killer_of(&self, username: &str) -> Option<&str> {
+    self.final_deaths.get(&username.to_lowercase())?.as_deref()
+  }
+
+  pub fn beds_broken_by(&self, username: &str) -> u32 {
+    self.beds_broken.get(&username.to_lowercase()).copied().unwrap_or(0)
+  }
+
+  pub fn add_final_kill(&mut self, victim: &str, killer: Option<&str>) {
+    self
+      .final_deaths
+      .insert(victim.to_lowercase(), killer.map(|x| x.to_string()));
+
+    if let Some(killer) = killer {
+      *self.final_kills.entry(killer.to_lowercase()).or_default() += 1;
+    }
+  }
+
+  pub fn add_bed_destroyed(&mut self, team: Option<Team>, breaker: &str) {
+    if let Some(team) = team {
+      self.destroyed_beds.insert(team);
+    }
+
+    *self.beds_broken.entry(breaker.to_lowercase()).or_default() += 1;
+  }
+
+  /// Used when the log didn't say if we won. Without an own team we can't know
+  pub fn infer_result(&self, own_username: &str) -> Option<GameResult> {
+    let own_team = self.team_of(own_username)?;
+
+    if self.is_dead(own_username) || self.eliminated_teams.contains(&own_team) {
+      Some(GameResult::Loss)
+    } else {
+      Some(GameResult::Win)
+    }
+  }
+
+  /// A player is out of the game after a final death, or when their whole team is eliminated
+  pub fn is_dead(&self, username: &str) -> bool {
+    self.final_deaths.contains_key(&username.to_lowercase())
+      || self
+        .team_of(username)
+        .is_some_and(|team| self.eliminated_teams.contains(&team))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn in_game() -> MatchState {
+    let mut match_state = MatchState::started("2024-01-01 12:00".to_string(), Location::default(), vec![]);
+    match_state.set_team("Me", Team::Red);
+    match_state.set_team("Foo", Team::Blue);
+    match_state
+  }
+
+  #[test]
+  fn result_needs_own_team() {
+    let match_state = in_game();
+
+    assert_eq!(match_state.infer_result("Someone"), None);
+    assert_eq!(match_state.infer_result("me"), Some(GameResult::Win));
+  }
+
+  #[test]
+  fn final_death_is_a_loss() {
+    let mut match_state = in_game();
+    match_state.add_final_kill("Me", Some("Foo"));
+
+    assert!(match_state.is_dead("me"));
+    assert!(!match_state.is_dead("Foo"));
+    assert_eq!(match_state.killer_of("Me"), Some("Foo"));
+    assert_eq!(match_state.final_kills_of("foo"), 1);
+    assert_eq!(match_state.infer_result("Me"), Some(GameResult::Loss));
+  }
+
+  #[test]
+  fn eliminated_team_is_dead() {
+    let mut match_state = in_game();
+    match_state.eliminated_teams.insert(Team::Red);
+
+    assert!(match_state.is_dead("Me"));
+    assert_eq!(match_state.infer_result("Me"), Some(GameResult::Loss));
+  }
+
+  #[test]
+  fn bed_counts() {
+    let mut match_state = in_game();
+    match_state.add_bed_destroyed(Some(Team::Blue), "Me");
+    match_state.add_bed_destroyed(None, "Me");
+
+    assert!(match_state.destroyed_beds.contains(&Team::Blue));
+    assert_eq!(match_state.beds_broken_by("me"), 2);
+    assert!(!match_state.is_dead("Foo"));
+  }
+
+  #[test]
+  fn ended_keeps_only_the_result() {
+    let match_state = MatchState::ended(Some(GameResult::Win));
+
+    assert!(match_state.ended && !match_state.in_progress);
+    assert_eq!(match_state.result, Some(GameResult::Win));
+    assert_eq!(match_state.team_of("Me"), None);
+  }
+}
@@ -0,0 +1,92 @@
+use crate::data::PlayerStats;
+use crate::game::{GameResult, Location, MatchState, Team};
+use serde::{Deserialize, Serialize};
+use std::fs::write;
+
+const HISTORY_FILE: &str = "history.json";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RosterEntry {
+  pub stats: PlayerStats, // as they were when the game started
+  pub team: Option<Team>,
+  pub final_kills: u32,
+  pub beds_broken: u32,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)] // so fields added later don't make the saved history unreadable
+pub struct MatchRecord {
+  pub started: String,
+  pub ended: String,
+  pub location: Location,
+  pub roster: Vec<RosterEntry>,
+  pub result: Option<GameResult>,
+  pub killed_by: Option<String>, // who final killed us
+}
+
+impl MatchRecord {
+  pub fn new(match_state: &MatchState, own_username: Option<&str>, ended: String) -> Self {
+    Self {
+      started: match_state.started.clone(),
+      ended,
+      location: match_state.location.clone(),
+      roster: match_state
+        .roster
+        .iter()
+        .map(|stats| RosterEntry {
+          stats: stats.clone(),
+          team: match_state.team_of(&stats.username),
+          final_kills: match_state.final_kills_of(&stats.username),
+          beds_broken: match_state.beds_broken_by(&stats.username),
+        })
+        .collect(),
+      result: match_state.result,
+      killed_by: own_username.and_then(|own| match_state.killer_of(own).map(|x| x.to_string())),
+    }
+  }
+}
+
+/// Every game played, saved to `history.json` (if it was loaded from it)
+#[derive(Debug, Default)]
+pub struct MatchHistory {
+  pub matches: Vec<MatchRecord>,
+  file_name: Option<&'static str>,
+}
+
+impl MatchHistory {
+  pub fn load() -> Self {
+    Self {
+      matches: crate::load_json_file(HISTORY_FILE),
+      file_name: Some(HISTORY_FILE),
+    }
+  }
+
+  fn save(&self) {
+    let Some(file_name) = self.file_name else {
+      return;
+    };
+
+    if let Err(e) = self.export(file_name) {
+      dbg!(e);
+    }
+  }
+
+  pub fn export(&self, file_name: &str) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&self.matches).map_err(|e| e.to_string())?;
+    write(file_name, json).map_err(|e| e.to_string())
+  }
+
+  pub fn add(&mut self, record: MatchRecord) {
+    self.matches.push(record);
+    self.save();
+  }
+
+  /// The result can be logged after the game was already added
+  pub fn set_last_result(&mut self, result: GameResult) {
+    if let Some(record) = self.matches.last_mut() {
+      record.result = Some(result);
+      self.save();
+    }
+  }
+}
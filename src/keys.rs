@@ -0,0 +1,285 @@
+use itertools::Itertools;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// how long a rate limited key is skipped if hypixel doesn't say when the limit resets
+const DEFAULT_RATE_LIMIT_RESET: Duration = Duration::from_secs(60);
+// hypixel can say the limit resets in 0 seconds, which would retry the key right away
+const MIN_RATE_LIMIT_RESET: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStatus {
+  Unused,
+  Valid,
+  Invalid,
+  RateLimited,
+}
+
+impl KeyStatus {
+  pub fn name(self) -> &'static str {
+    match self {
+      KeyStatus::Unused => "Not used yet",
+      KeyStatus::Valid => "Valid",
+      KeyStatus::Invalid => "Invalid",
+      KeyStatus::RateLimited => "Rate limited",
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+  pub key: String,
+  pub status: KeyStatus,
+  pub requests: u32,
+  pub remaining: Option<u32>, // requests left until the rate limit, from the last response
+  limited_until: Option<Instant>,
+}
+
+impl ApiKey {
+  fn new(key: String) -> Self {
+    Self {
+      key,
+      status: KeyStatus::Unused,
+      requests: 0,
+      remaining: None,
+      limited_until: None,
+    }
+  }
+
+  /// The start of the key, so it can be told apart without showing all of it
+  pub fn masked(&self) -> String {
+    format!("{}...", self.key.chars().take(8).collect::<String>())
+  }
+
+  fn rate_limit(&mut self, reset: Option<Duration>) {
+    let reset = reset.unwrap_or(DEFAULT_RATE_LIMIT_RESET).max(MIN_RATE_LIMIT_RESET);
+    self.status = KeyStatus::RateLimited;
+    self.limited_until = Some(Instant::now() + reset);
+  }
+
+  fn is_usable(&self) -> bool {
+    match self.status {
+      KeyStatus::Invalid => false,
+      KeyStatus::RateLimited => self.limited_until.is_some_and(|until| until <= Instant::now()),
+      _ => true,
+    }
+  }
+}
+
+#[derive(Debug)]
+struct ApiKeys {
+  keys: Vec<ApiKey>,
+  next: usize,
+  missing: bool, // no keys, and no proxy which has them
+}
+
+impl ApiKeys {
+  /// The next key which isn't invalid or rate limited, taking turns between them
+  fn next_key(&mut self) -> Option<String> {
+    let count = self.keys.len();
+    let start = self.next;
+    let index = (0..count)
+      .map(|offset| (start + offset) % count)
+      .find(|index| self.keys[*index].is_usable())?;
+
+    self.next = index + 1;
+    let key = &mut self.keys[index];
+    key.requests += 1;
+    Some(key.key.clone())
+  }
+}
+
+static API_KEYS: Mutex<ApiKeys> = Mutex::new(ApiKeys {
+  keys: vec![],
+  next: 0,
+  missing: false,
+});
+
+/// What's wrong with the api keys, shown as a banner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyWarning {
+  Missing,
+  AllInvalid,
+  AllRateLimited,
+  SomeInvalid { invalid: usize, total: usize },
+}
+
+impl KeyWarning {
+  pub fn message(&self) -> String {
+    match self {
+      KeyWarning::Missing => {
+        "No API key. Create one at https://developer.hypixel.net and set api_key in settings.toml".to_string()
+      }
+      KeyWarning::AllInvalid => "The API key is invalid or expired, so no stats can be loaded. Create a new one at \
+        https://developer.hypixel.net and update api_key in settings.toml"
+        .to_string(),
+      KeyWarning::AllRateLimited => {
+        "All API keys are rate limited. Stats will load again soon, or add more keys to api_key in settings.toml"
+          .to_string()
+      }
+      KeyWarning::SomeInvalid { invalid, total } => format!(
+        "{} of {} API keys are invalid or expired and are skipped. Replace them in settings.toml",
+        invalid, total
+      ),
+    }
+  }
+
+  /// If no stats can be loaded at all
+  pub fn is_blocking(&self) -> bool {
+    !matches!(self, KeyWarning::SomeInvalid { .. })
+  }
+}
+
+/// `api_key` can be a single key or a list of them
+fn get_keys_setting() -> Vec<String> {
+  match crate::get_optional_toml_value("settings.toml", "api_key") {
+    Some(toml::Value::Array(keys)) => keys.iter().filter_map(|k| k.as_str().map(|x| x.to_string())).collect(),
+    Some(key) => vec![key
+      .as_str()
+      .expect("api_key should be a string or a list of strings")
+      .to_string()],
+    None => vec![],
+  }
+}
+
+/// Picks up keys which were added to (or removed from) settings.toml, keeping the usage of the others
+fn sync_keys(api_keys: &mut ApiKeys) {
+  let setting = get_keys_setting();
+
+  api_keys.missing = setting.is_empty() && !crate::fetching::uses_custom_hypixel_url();
+
+  api_keys.keys.retain(|k| setting.contains(&k.key));
+  for key in setting.into_iter().unique() {
+    if !api_keys.keys.iter().any(|k| k.key == key) {
+      api_keys.keys.push(ApiKey::new(key));
+    }
+  }
+}
+
+/// So the keys show up in the ui before they're used
+pub fn load_keys() {
+  sync_keys(&mut API_KEYS.lock().unwrap());
+}
+
+pub fn has_keys() -> bool {
+  !get_keys_setting().is_empty()
+}
+
+pub fn next_key() -> Option<String> {
+  let mut api_keys = API_KEYS.lock().unwrap();
+  sync_keys(&mut api_keys);
+  api_keys.next_key()
+}
+
+/// Updates the status of the key from a response to a request made with it
+pub fn report_response(key: &str, response: &reqwest::blocking::Response) {
+  let header = |name: &str| {
+    response
+      .headers()
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .and_then(|v| v.parse::<u64>().ok())
+  };
+
+  let mut api_keys = API_KEYS.lock().unwrap();
+  let Some(api_key) = api_keys.keys.iter_mut().find(|k| k.key == key) else {
+    return;
+  };
+
+  api_key.remaining = header("RateLimit-Remaining").map(|x| x as u32);
+
+  match response.status() {
+    reqwest::StatusCode::FORBIDDEN => {
+      println!("API key {} is invalid", api_key.masked());
+      api_key.status = KeyStatus::Invalid;
+    }
+    reqwest::StatusCode::TOO_MANY_REQUESTS => {
+      println!("API key {} is rate limited", api_key.masked());
+      api_key.rate_limit(header("RateLimit-Reset").map(Duration::from_secs));
+    }
+    _ => {
+      api_key.status = KeyStatus::Valid;
+      api_key.limited_until = None;
+    }
+  }
+}
+
+/// The keys with their usage, to show in the ui
+pub fn get_keys() -> Vec<ApiKey> {
+  API_KEYS.lock().unwrap().keys.clone()
+}
+
+pub fn get_warning() -> Option<KeyWarning> {
+  let api_keys = API_KEYS.lock().unwrap();
+  if api_keys.missing {
+    return Some(KeyWarning::Missing);
+  }
+
+  let total = api_keys.keys.len();
+  let invalid = api_keys.keys.iter().filter(|k| k.status == KeyStatus::Invalid).count();
+
+  if total == 0 {
+    None
+  } else if invalid == total {
+    Some(KeyWarning::AllInvalid)
+  } else if !api_keys.keys.iter().any(|k| k.is_usable()) {
+    Some(KeyWarning::AllRateLimited)
+  } else if invalid > 0 {
+    Some(KeyWarning::SomeInvalid { invalid, total })
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn api_keys(keys: &[&str]) -> ApiKeys {
+    ApiKeys {
+      keys: keys.iter().map(|k| ApiKey::new(k.to_string())).collect(),
+      next: 0,
+      missing: false,
+    }
+  }
+
+  #[test]
+  fn takes_turns() {
+    let mut api_keys = api_keys(&["a", "b", "c"]);
+    let used = (0..4).filter_map(|_| api_keys.next_key()).collect_vec();
+
+    assert_eq!(used, vec!["a", "b", "c", "a"]);
+    assert_eq!(api_keys.keys[0].requests, 2);
+  }
+
+  #[test]
+  fn skips_invalid_and_rate_limited_keys() {
+    let mut api_keys = api_keys(&["a", "b", "c"]);
+    api_keys.keys[0].status = KeyStatus::Invalid;
+    api_keys.keys[1].rate_limit(Some(Duration::from_secs(60)));
+
+    assert_eq!(api_keys.next_key().as_deref(), Some("c"));
+    assert_eq!(api_keys.next_key().as_deref(), Some("c"));
+
+    api_keys.keys[2].status = KeyStatus::Invalid;
+    assert_eq!(api_keys.next_key(), None);
+  }
+
+  #[test]
+  fn rate_limited_keys_are_used_again_after_the_reset() {
+    let mut api_keys = api_keys(&["a"]);
+    api_keys.keys[0].rate_limit(None);
+    assert_eq!(api_keys.next_key(), None);
+
+    api_keys.keys[0].limited_until = Some(Instant::now());
+    assert_eq!(api_keys.next_key().as_deref(), Some("a"));
+  }
+
+  #[test]
+  fn a_reset_of_zero_still_waits() {
+    let mut api_keys = api_keys(&["a"]);
+    api_keys.keys[0].rate_limit(Some(Duration::ZERO));
+
+    assert_eq!(api_keys.next_key(), None);
+  }
+}
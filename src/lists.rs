@@ -0,0 +1,109 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::write;
+
+const LISTS_FILE: &str = "lists.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListReason {
+  Cheater,
+  Sniper,
+  Toxic,
+  Friend,
+  Teammate,
+}
+
+impl ListReason {
+  pub const ALL: [ListReason; 5] = [
+    ListReason::Cheater,
+    ListReason::Sniper,
+    ListReason::Toxic,
+    ListReason::Friend,
+    ListReason::Teammate,
+  ];
+
+  pub fn is_blacklist(self) -> bool {
+    matches!(self, ListReason::Cheater | ListReason::Sniper | ListReason::Toxic)
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ListReason::Cheater => "Cheater",
+      ListReason::Sniper => "Sniper",
+      ListReason::Toxic => "Toxic",
+      ListReason::Friend => "Friend",
+      ListReason::Teammate => "Teammate",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListEntry {
+  pub username: String, // last known username, the entry itself is keyed by uuid
+  pub reason: ListReason,
+  pub note: String,
+  pub date: String,
+}
+
+/// Blacklisted and whitelisted players, keyed by uuid. Saved to `lists.json` on every change.
+#[derive(Debug, Default)]
+pub struct PlayerLists {
+  entries: HashMap<String, ListEntry>,
+}
+
+impl PlayerLists {
+  pub fn load() -> Self {
+    Self {
+      entries: crate::load_json_file(LISTS_FILE),
+    }
+  }
+
+  fn save(&self) {
+    let json = serde_json::to_string_pretty(&self.entries).expect("Serializing the lists went wrong");
+
+    if let Err(e) = write(LISTS_FILE, json) {
+      dbg!(e);
+    }
+  }
+
+  pub fn get(&self, uuid: &str) -> Option<&ListEntry> {
+    self.entries.get(uuid)
+  }
+
+  pub fn entries(&self) -> impl Iterator<Item = (&String, &ListEntry)> {
+    self
+      .entries
+      .iter()
+      .sorted_by(|(_, e1), (_, e2)| e1.username.cmp(&e2.username))
+  }
+
+  pub fn set(&mut self, uuid: &str, username: &str, reason: ListReason, note: &str) {
+    self.entries.insert(
+      uuid.to_string(),
+      ListEntry {
+        username: username.to_string(),
+        reason,
+        note: note.to_string(),
+        date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+      },
+    );
+    self.save();
+  }
+
+  pub fn remove(&mut self, uuid: &str) {
+    if self.entries.remove(uuid).is_some() {
+      self.save();
+    }
+  }
+
+  /// Keeps the stored username up to date if the player has changed their name
+  pub fn update_username(&mut self, uuid: &str, username: &str) {
+    if let Some(entry) = self.entries.get_mut(uuid) {
+      if entry.username != username {
+        entry.username = username.to_string();
+        self.save();
+      }
+    }
+  }
+}
@@ -0,0 +1,355 @@
+use crate::data;
+use crate::data::{PlayerStats, StatusCheck};
+use crate::fetching;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+// how many players are looked up at the same time, if lookup_concurrency isn't set
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Why a player was looked up, which decides what happens with the stats when they're loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupPurpose {
+  Join,      // joined the lobby, can alert
+  LobbyList, // listed by /who, can alert
+  Manual,    // added with the "Add player" button
+  OwnStats,
+  NewSession, // own stats, which a new session is started from
+}
+
+impl LookupPurpose {
+  /// Lookups someone is waiting for go first, bulk /who fills last
+  fn priority(self) -> u8 {
+    match self {
+      LookupPurpose::Manual => 3,
+      LookupPurpose::OwnStats | LookupPurpose::NewSession => 2,
+      LookupPurpose::Join => 1,
+      LookupPurpose::LobbyList => 0,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct PlayerLoaded {
+  pub player: PlayerStats,
+  pub purpose: LookupPurpose,
+  pub username: String, // what it was looked up as, which isn't the name in the stats if they changed their name
+}
+
+#[derive(Debug)]
+pub struct StatusLoaded {
+  pub uuid: String,
+  pub status: StatusCheck,
+}
+
+/// One lookup of a player, which any number of requests for the same player are merged into
+#[derive(Debug)]
+struct Job {
+  username: String,
+  uuid: Option<String>, // if it was already resolved
+  chat_rank: Option<String>,
+  purposes: Vec<(LookupPurpose, String)>, // with the username of that request
+}
+
+impl Job {
+  fn priority(&self) -> u8 {
+    self.purposes.iter().map(|(p, _)| p.priority()).max().unwrap_or(0)
+  }
+
+  fn merge(&mut self, username: String, uuid: Option<String>, chat_rank: Option<String>, purpose: LookupPurpose) {
+    if self.uuid.is_none() {
+      self.uuid = uuid;
+    }
+    if self.chat_rank.is_none() {
+      self.chat_rank = chat_rank;
+    }
+    if !self
+      .purposes
+      .iter()
+      .any(|(p, u)| *p == purpose && u.to_lowercase() == username.to_lowercase())
+    {
+      self.purposes.push((purpose, username));
+    }
+  }
+}
+
+/// The lookups which are waiting or running. A player is found by their username, or by their uuid if it's known
+/// (e.g. a player who changed their name)
+#[derive(Debug)]
+struct LookupQueue {
+  concurrency: usize,
+  // keyed by lowercase username
+  queued: Vec<(String, Job)>,
+  running: HashMap<String, Job>,
+}
+
+impl LookupQueue {
+  fn new(concurrency: usize) -> Self {
+    Self {
+      concurrency,
+      queued: vec![],
+      running: HashMap::new(),
+    }
+  }
+
+  /// Merges the request into the lookup of the same player if there is one
+  fn request(&mut self, username: String, uuid: Option<String>, chat_rank: Option<String>, purpose: LookupPurpose) {
+    let key = username.to_lowercase();
+    let is_same = |k: &String, job: &Job| *k == key || (uuid.is_some() && job.uuid == uuid);
+
+    if let Some((_, job)) = self.running.iter_mut().find(|(k, job)| is_same(k, job)) {
+      job.merge(username, uuid, chat_rank, purpose);
+    } else if let Some((_, job)) = self.queued.iter_mut().find(|(k, job)| is_same(k, job)) {
+      job.merge(username, uuid, chat_rank, purpose);
+    } else {
+      let job = Job {
+        username: username.clone(),
+        uuid,
+        chat_rank,
+        purposes: vec![(purpose, username)],
+      };
+      self.queued.push((key, job));
+    }
+  }
+
+  /// The key and username of the next lookup to run, unless enough are running already.
+  /// The first of the most important ones
+  fn start_next(&mut self) -> Option<(String, String)> {
+    if self.running.len() >= self.concurrency {
+      return None;
+    }
+
+    let index = self
+      .queued
+      .iter()
+      .enumerate()
+      .max_by_key(|(index, (_, job))| (job.priority(), Reverse(*index)))
+      .map(|(index, _)| index)?;
+    let (key, job) = self.queued.remove(index);
+
+    let username = job.username.clone();
+    self.running.insert(key.clone(), job);
+    Some((key, username))
+  }
+
+  fn finish(&mut self, key: &str) -> Option<Job> {
+    self.running.remove(key)
+  }
+}
+
+#[derive(Debug)]
+enum Message {
+  Request {
+    username: String,
+    uuid: Option<String>,
+    chat_rank: Option<String>,
+    purpose: LookupPurpose,
+  },
+  Done {
+    key: String,
+    player: Box<PlayerStats>,
+  },
+}
+
+fn get_concurrency() -> usize {
+  crate::get_optional_toml_value("settings.toml", "lookup_concurrency")
+    .and_then(|v| v.as_integer())
+    .map_or(DEFAULT_CONCURRENCY, |x| x.max(1) as usize)
+}
+
+/// Fetches player stats in the background, a few at a time. Requests for a player which is already queued or being
+/// fetched are merged into that lookup. The results are sent back as `PlayerLoaded`
+#[derive(Debug, Clone)]
+pub struct LookupService {
+  messages: Sender<Message>,
+  statuses: Sender<StatusLoaded>,
+}
+
+impl LookupService {
+  pub fn start() -> (Self, Receiver<PlayerLoaded>, Receiver<StatusLoaded>) {
+    let (messages, message_receiver) = channel();
+    let (results, result_receiver) = channel();
+    let (statuses, status_receiver) = channel();
+
+    let worker_messages = messages.clone();
+    thread::spawn(move || dispatch(message_receiver, worker_messages, results, get_concurrency()));
+
+    (Self { messages, statuses }, result_receiver, status_receiver)
+  }
+
+  /// Checks if the player is online, and what they're playing. The result is sent back as `StatusLoaded`
+  pub fn lookup_status(&self, uuid: &str) {
+    let uuid = uuid.to_string();
+    let statuses = self.statuses.clone();
+
+    thread::spawn(move || {
+      let status = data::get_online_status(&uuid); // takes some time
+      let _ = statuses.send(StatusLoaded { uuid, status });
+    });
+  }
+
+  /// Resolves the uuids of the whole lobby with a few bulk requests first, instead of one request per player
+  pub fn lookup_lobby_list(&self, usernames: Vec<String>) {
+    let service = self.clone();
+
+    thread::spawn(move || {
+      if let Err(e) = fetching::get_uuids(&usernames) {
+        // the lookups resolve them one by one instead
+        dbg!(e);
+      }
+
+      for username in usernames {
+        service.lookup(&username, None, LookupPurpose::LobbyList);
+      }
+    });
+  }
+
+  pub fn lookup(&self, username: &str, chat_rank: Option<String>, purpose: LookupPurpose) {
+    let message = Message::Request {
+      username: username.to_string(),
+      uuid: fetching::get_cached_uuid(username),
+      chat_rank,
+      purpose,
+    };
+
+    if self.messages.send(message).is_err() {
+      println!("The lookup service isn't running, couldn't look up {}", username);
+    }
+  }
+}
+
+fn dispatch(
+  messages: Receiver<Message>,
+  worker_messages: Sender<Message>,
+  results: Sender<PlayerLoaded>,
+  concurrency: usize,
+) {
+  let mut queue = LookupQueue::new(concurrency);
+
+  for message in messages {
+    match message {
+      Message::Request {
+        username,
+        uuid,
+        chat_rank,
+        purpose,
+      } => queue.request(username, uuid, chat_rank, purpose),
+      Message::Done { key, mut player } => {
+        let Some(job) = queue.finish(&key) else {
+          continue;
+        };
+
+        player.chat_rank = job.chat_rank;
+        for (purpose, username) in job.purposes {
+          // the receiver is only gone when the app is closing
+          if results
+            .send(PlayerLoaded {
+              player: (*player).clone(),
+              purpose,
+              username,
+            })
+            .is_err()
+          {
+            return;
+          }
+        }
+      }
+    }
+
+    while let Some((key, username)) = queue.start_next() {
+      let worker_messages = worker_messages.clone();
+      thread::spawn(move || {
+        let player = data::get_stats(&username); // takes some time
+        let _ = worker_messages.send(Message::Done {
+          key,
+          player: Box::new(player),
+        });
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn request(queue: &mut LookupQueue, username: &str, uuid: Option<&str>, purpose: LookupPurpose) {
+    queue.request(username.to_string(), uuid.map(|x| x.to_string()), None, purpose);
+  }
+
+  fn started(queue: &mut LookupQueue) -> Vec<String> {
+    std::iter::from_fn(|| queue.start_next().map(|(_, username)| username)).collect()
+  }
+
+  #[test]
+  fn merges_requests_for_the_same_player() {
+    let mut queue = LookupQueue::new(4);
+    request(&mut queue, "Foo", None, LookupPurpose::LobbyList);
+    request(&mut queue, "foo", None, LookupPurpose::Join);
+    queue.request("FOO".to_string(), None, Some("MVP+".to_string()), LookupPurpose::Join);
+
+    assert_eq!(started(&mut queue), vec!["Foo"]);
+
+    // a request for a running lookup is merged into it too
+    request(&mut queue, "Foo", None, LookupPurpose::Manual);
+    assert!(started(&mut queue).is_empty());
+
+    let job = queue.finish("foo").unwrap();
+    assert_eq!(
+      job.purposes.iter().map(|(p, _)| *p).collect::<Vec<_>>(),
+      vec![LookupPurpose::LobbyList, LookupPurpose::Join, LookupPurpose::Manual]
+    );
+    assert_eq!(job.chat_rank.as_deref(), Some("MVP+"));
+  }
+
+  #[test]
+  fn merges_requests_with_the_same_uuid() {
+    let mut queue = LookupQueue::new(4);
+    request(&mut queue, "OldName", Some("abc"), LookupPurpose::Manual);
+    request(&mut queue, "NewName", Some("abc"), LookupPurpose::Join);
+    request(&mut queue, "Other", None, LookupPurpose::Join);
+
+    assert_eq!(started(&mut queue), vec!["OldName", "Other"]);
+
+    // each result is sent with the name it was requested as, so the lobby finds the player who joined
+    request(&mut queue, "NewestName", Some("abc"), LookupPurpose::LobbyList);
+    assert_eq!(
+      queue.finish("oldname").unwrap().purposes,
+      vec![
+        (LookupPurpose::Manual, "OldName".to_string()),
+        (LookupPurpose::Join, "NewName".to_string()),
+        (LookupPurpose::LobbyList, "NewestName".to_string())
+      ]
+    );
+  }
+
+  #[test]
+  fn most_important_first_then_in_order() {
+    let mut queue = LookupQueue::new(10);
+    request(&mut queue, "A", None, LookupPurpose::LobbyList);
+    request(&mut queue, "B", None, LookupPurpose::Join);
+    request(&mut queue, "C", None, LookupPurpose::LobbyList);
+    request(&mut queue, "D", None, LookupPurpose::Manual);
+    request(&mut queue, "E", None, LookupPurpose::Join);
+    request(&mut queue, "F", None, LookupPurpose::OwnStats);
+
+    assert_eq!(started(&mut queue), vec!["D", "F", "B", "E", "A", "C"]);
+  }
+
+  #[test]
+  fn runs_at_most_concurrency_lookups() {
+    let mut queue = LookupQueue::new(2);
+    for username in ["A", "B", "C", "D"] {
+      request(&mut queue, username, None, LookupPurpose::Join);
+    }
+
+    assert_eq!(started(&mut queue), vec!["A", "B"]);
+
+    queue.finish("a");
+    assert_eq!(started(&mut queue), vec!["C"]);
+    assert!(queue.finish("unknown").is_none());
+    assert!(started(&mut queue).is_empty());
+  }
+}
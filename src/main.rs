@@ -5,10 +5,20 @@ use std::fs::read_to_string;
 
 use eframe::egui::{IconData, ViewportBuilder};
 
+mod alerts;
 mod app;
 mod data;
+mod events;
 mod fetching;
+mod game;
+mod history;
+mod keys;
+mod lists;
+mod lookup;
 mod parsing;
+mod serving;
+mod session;
+mod watching;
 
 fn get_toml_value(file_name: &str, key: &str) -> toml::Value {
   let file = read_to_string(file_name).unwrap_or_else(|_| panic!("{} file not found", file_name));
@@ -22,9 +32,49 @@ fn get_toml_value(file_name: &str, key: &str) -> toml::Value {
     .clone()
 }
 
+fn get_optional_toml_value(file_name: &str, key: &str) -> Option<toml::Value> {
+  read_to_string(file_name)
+    .ok()?
+    .parse::<toml::Value>()
+    .ok()?
+    .get(key)
+    .cloned()
+}
+
+fn get_optional_string_setting(key: &str) -> Option<String> {
+  get_optional_toml_value("settings.toml", key).and_then(|v| v.as_str().map(|x| x.to_string()))
+}
+
+fn get_string_setting(key: &str, default: &str) -> String {
+  get_optional_string_setting(key).unwrap_or_else(|| default.to_string())
+}
+
+/// Reads one of the json files jahbo saves to. A file which can't be parsed is backed up first, because it's saved
+/// over on the next change
+fn load_json_file<T: serde::de::DeserializeOwned + Default>(file_name: &str) -> T {
+  let Ok(file) = read_to_string(file_name) else {
+    return T::default(); // not created yet
+  };
+
+  serde_json::from_str(&file).unwrap_or_else(|e| {
+    let backup = format!("{}.{}.bak", file_name, chrono::Local::now().format("%Y%m%d%H%M%S"));
+    println!("{} is not valid ({}), backing it up to {}", file_name, e, backup);
+
+    if let Err(e) = std::fs::copy(file_name, &backup) {
+      dbg!(e);
+    }
+    T::default()
+  })
+}
+
 fn main() {
   // TODO: generate the settings.toml file (if it's missing) and ask to fill it in
 
+  if std::env::args().nth(1).as_deref() == Some("serve") {
+    serving::serve();
+    return;
+  }
+
   let icon_bytes = include_bytes!("../assets/icon.png");
   let icon = image::load_from_memory(icon_bytes).unwrap();
 
@@ -1,177 +1,896 @@
-use crate::data;
+use crate::game::{GameResult, Location, Team};
+use crate::watching::LogWatcher;
 use encoding::all::UTF_8;
 use encoding::Encoding;
 use itertools::Itertools;
-use rayon::iter::IntoParallelRefIterator;
-use rayon::prelude::*;
 use regex::Regex;
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::sync::{Arc, Mutex};
+use std::borrow::Cow;
+use std::fs::{File, Metadata};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-const SLEEP_DURATION: u64 = 100;
+// how often to look for new log files matching the log_file patterns
+const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+// the loop runs at least every second, so this is only reached if reading or sending the lines is stuck
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+const RESTART_DELAY: Duration = Duration::from_secs(5);
 
-enum ParsedLine {
-  JoinedLobby { username: String },
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParserHealth {
+  Running,
+  Stalled, // hasn't read the log files in a while
+  Crashed(String),
+}
+
+impl ParserHealth {
+  pub fn of(data: &crate::app::AppData) -> Self {
+    if let Some(error) = &data.parser_crash {
+      return ParserHealth::Crashed(error.clone());
+    }
+
+    match data.parser_heartbeat {
+      Some(heartbeat) if heartbeat.elapsed() < STALL_TIMEOUT => ParserHealth::Running,
+      _ => ParserHealth::Stalled,
+    }
+  }
+}
+
+struct LogSource {
+  path: PathBuf,
+  reader: BufReader<File>,
+  bytes: Vec<u8>,
+  replaying: bool, // the lines from before jahbo was started are being read
+  file_id: Option<u128>,
+}
+
+/// Tells a file apart from the one which was at the same path before, e.g. after the client rotated the log
+fn get_file_id(metadata: &Metadata) -> Option<u128> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino() as u128)
+  }
+  #[cfg(not(unix))]
+  {
+    let created = metadata.created().ok()?;
+    Some(created.duration_since(std::time::UNIX_EPOCH).ok()?.as_nanos())
+  }
+}
+
+/// If the file at the path was replaced or truncated since it was opened, so it has to be read from the start again
+fn was_rotated(source: &mut LogSource) -> bool {
+  let Ok(metadata) = source.path.metadata() else {
+    return false;
+  };
+  let Ok(position) = source.reader.stream_position() else {
+    return false;
+  };
+
+  metadata.len() < position || get_file_id(&metadata) != source.file_id
+}
+
+/// `log_file` can be a path, a glob pattern (e.g. a whole directory of logs) or a list of them
+fn get_log_file_paths() -> Vec<PathBuf> {
+  let log_file = crate::get_toml_value("settings.toml", "log_file");
+
+  let patterns = match &log_file {
+    toml::Value::Array(patterns) => patterns.iter().filter_map(|p| p.as_str()).collect_vec(),
+    log_file => vec![log_file
+      .as_str()
+      .expect("log_file should be a string or a list of strings")],
+  };
+
+  patterns
+    .into_iter()
+    .flat_map(|pattern| match glob::glob(pattern) {
+      Ok(paths) => paths.filter_map(|path| path.ok()).collect_vec(),
+      Err(_) => vec![PathBuf::from(pattern)], // not a valid pattern, so use it as a path
+    })
+    .filter(|path| path.is_file())
+    .collect()
+}
+
+/// Opens the log files which aren't opened yet, and reopens the ones which were rotated. On the first scan only the
+/// most recently written file is read from the start, the others are probably from clients which aren't running.
+fn scan_log_files(sources: &mut Vec<LogSource>, watcher: &mut LogWatcher) {
+  let is_first_scan = sources.is_empty();
+
+  for source in sources.iter_mut() {
+    if !was_rotated(source) {
+      continue;
+    }
+    let Ok(file) = File::open(&source.path) else {
+      continue;
+    };
+
+    println!(
+      "Log file {} was rotated, reading it from the start",
+      source.path.display()
+    );
+    source.file_id = file.metadata().ok().as_ref().and_then(get_file_id);
+    source.reader = BufReader::new(file);
+    source.bytes.clear();
+    watcher.watch(&source.path);
+  }
+
+  let new_paths = get_log_file_paths()
+    .into_iter()
+    .filter(|path| !sources.iter().any(|s| &s.path == path))
+    .collect_vec();
+
+  let most_recent = new_paths
+    .iter()
+    .max_by_key(|path| path.metadata().and_then(|m| m.modified()).ok())
+    .cloned();
+
+  for path in new_paths {
+    let Ok(file) = File::open(&path) else {
+      continue;
+    };
+    let file_id = file.metadata().ok().as_ref().and_then(get_file_id);
+    let mut reader = BufReader::new(file);
+
+    let replaying = is_first_scan && Some(&path) == most_recent.as_ref();
+    if is_first_scan && !replaying {
+      if let Err(e) = reader.seek(SeekFrom::End(0)) {
+        dbg!(e);
+      }
+    }
+
+    println!("Reading log file {}", path.display());
+    watcher.watch(&path);
+    sources.push(LogSource {
+      path,
+      reader,
+      bytes: vec![],
+      replaying,
+      file_id,
+    });
+  }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParsedLine {
+  JoinedLobby { username: String, rank: Option<String> },
   LeftLobby { username: String },
   LobbyList { usernames: Vec<String> },
   GameStart,
+  Nick { username: String },
+  SettingUser { username: String },
+  PartyList { count: usize },
+  PartyMembers { usernames: Vec<String> },
+  PartyMemberLeft { username: String },
+  PartyDisbanded,
+  TeamMembers { members: Vec<(String, Team)> },
+  FinalKill { victim: String, killer: Option<String> },
+  BedDestroyed { team: Option<Team>, breaker: String }, // no team means it was our own bed
+  TeamEliminated { team: Team },
+  GameEnd { result: Option<GameResult> },
+  Location { location: Location },
   Nothing,
 }
 
-fn parse_line(line: &str) -> ParsedLine {
-  let joined_lobby_re = Regex::new(r"\[CHAT\] ([^ ]+) has joined").unwrap();
-  let left_lobby_re = Regex::new(r"\[CHAT\] ([^ ]+) has quit").unwrap();
-  let who_lobby_re = Regex::new(r"\[CHAT\] ONLINE: (.+)").unwrap();
-  // TODO: maybe there is better way of checking if a game has started
-  let game_start_re = Regex::new(r"\[CHAT\] The game starts in 1 seconds!").unwrap();
+/// A parsed line and the log file it came from
+#[derive(Debug)]
+pub struct LogEvent {
+  pub parsed_line: ParsedLine,
+  pub source: String,
+  pub time: String,  // when the line was written, e.g. "2024-05-01 18:30"
+  pub backlog: bool, // written before jahbo was started, so it was already handled back then
+}
 
-  if let Some(captures) = joined_lobby_re.captures(line) {
-    ParsedLine::JoinedLobby {
-      username: captures[1].to_string(),
-    }
-  } else if let Some(captures) = left_lobby_re.captures(line) {
-    ParsedLine::LeftLobby {
-      username: captures[1].to_string(),
+/// What the log parser sends to the ui
+#[derive(Debug)]
+pub enum LobbyEvent {
+  Log(LogEvent),
+  Heartbeat, // sent every time the parser goes through its loop
+  ParserCrashed(String),
+}
+
+/// "[MVP+] Foo" -> (Some("MVP+"), "Foo")
+fn split_rank(name: &str) -> (Option<String>, String) {
+  let name = name.trim();
+
+  match name.rsplit_once(' ') {
+    Some((rank, username)) => (
+      Some(rank.trim_start_matches('[').trim_end_matches(']').to_string()),
+      username.to_string(),
+    ),
+    None => (None, name.to_string()),
+  }
+}
+
+fn strip_rank(name: &str) -> String {
+  split_rank(name).1
+}
+
+/// The regexes are compiled once, because every line of the log is parsed
+struct LineParser {
+  joined_lobby_re: Regex,
+  left_lobby_re: Regex,
+  who_lobby_re: Regex,
+  game_start_re: Regex,
+  nick_re: Regex,
+  setting_user_re: Regex,
+  party_list_re: Regex,
+  party_list_members_re: Regex,
+  party_joined_re: Regex,
+  party_you_joined_re: Regex,
+  party_partying_with_re: Regex,
+  party_left_re: Regex,
+  party_disbanded_re: Regex,
+  team_chat_re: Regex,
+  final_kill_re: Regex,
+  killer_re: Regex,
+  bed_destroyed_re: Regex,
+  team_eliminated_re: Regex,
+  first_killer_re: Regex,
+  game_end_title_re: Regex,
+  experience_re: Regex,
+  locraw_re: Regex,
+  formatting_code_re: Regex,
+  colored_name_re: Regex,
+  time_re: Regex,
+}
+
+impl LineParser {
+  fn new() -> Self {
+    Self {
+      // names can have a rank prefix
+      joined_lobby_re: Regex::new(r"\[CHAT\] (?:\[([^\]]+)\] )?([A-Za-z0-9_]{1,16}) has joined").unwrap(),
+      left_lobby_re: Regex::new(r"\[CHAT\] (?:\[[^\]]+\] )?([A-Za-z0-9_]{1,16}) has quit").unwrap(),
+      who_lobby_re: Regex::new(r"\[CHAT\] ONLINE: (.+)").unwrap(),
+      // TODO: maybe there is better way of checking if a game has started
+      game_start_re: Regex::new(r"\[CHAT\] The game starts in 1 seconds!").unwrap(),
+      // nicked players in the tab list, as printed by some clients (not a chat message, so the whole line)
+      nick_re: Regex::new(r"^\[[\d:]+\] \[[^\]]+\]: Nick: ([A-Za-z0-9_]{1,16})\s*$").unwrap(),
+      // printed by the client on startup, the whole line so it can't be typed in chat
+      setting_user_re: Regex::new(r"^\[[\d:]+\] \[[^\]]+\]: Setting user: ([A-Za-z0-9_]{1,16})\s*$").unwrap(),
+      // party messages (names can have a rank prefix)
+      party_list_re: Regex::new(r"\[CHAT\] Party Members \((\d+)\)").unwrap(),
+      party_list_members_re: Regex::new(r"\[CHAT\] Party (?:Leader|Moderators|Members): (.+)").unwrap(),
+      party_joined_re: Regex::new(r"\[CHAT\] (?:\[[^\]]+\] )?([A-Za-z0-9_]{1,16}) joined the party\.").unwrap(),
+      party_you_joined_re: Regex::new(r"\[CHAT\] You have joined (?:\[[^\]]+\] )?([A-Za-z0-9_]{1,16})'s party!").unwrap(),
+      party_partying_with_re: Regex::new(r"\[CHAT\] You'll be partying with: (.+)").unwrap(),
+      party_left_re: Regex::new(
+        r"\[CHAT\] (?:\[[^\]]+\] )?([A-Za-z0-9_]{1,16}) has (?:left|been removed from) the party\.",
+      )
+      .unwrap(),
+      party_disbanded_re: Regex::new(
+        r"\[CHAT\] (?:You left the party\.|You are not currently in a party\.|You have been kicked from the party|(?:\[[^\]]+\] )?[A-Za-z0-9_]{1,16} has disbanded the party!|The party was disbanded)",
+      )
+      .unwrap(),
+      // in game chat, e.g. "[SHOUT] [123✫] [RED] [MVP+] Foo: hi"
+      team_chat_re: Regex::new(
+        r"\[CHAT\] (?:\[SHOUT\] )?(?:\[\d+\W\] )?\[(RED|BLUE|GREEN|YELLOW|AQUA|WHITE|PINK|GRAY)\] (?:\[[^\]]+\] )?([A-Za-z0-9_]{1,16}): ",
+      )
+      .unwrap(),
+      // e.g. "Foo was knocked into the void by Bar. FINAL KILL!"
+      final_kill_re: Regex::new(r"\[CHAT\] ([A-Za-z0-9_]{1,16}) (.+)\. FINAL KILL!").unwrap(),
+      killer_re: Regex::new(r"\bby ([A-Za-z0-9_]{1,16})").unwrap(),
+      // e.g. "BED DESTRUCTION > Red Bed was destroyed by Foo!" or "BED DESTRUCTION > Your Bed was ..."
+      bed_destroyed_re: Regex::new(r"\[CHAT\] BED DESTRUCTION > (\w+) Bed .+ by ([A-Za-z0-9_]{1,16})").unwrap(),
+      team_eliminated_re: Regex::new(r"\[CHAT\] TEAM ELIMINATED > (\w+) Team has been eliminated!").unwrap(),
+      // the end of game summary, titles (some clients log them) and rewards
+      first_killer_re: Regex::new(r"\[CHAT\]\s+1st Killer - ").unwrap(),
+      // the whole line, so it can't be typed in chat
+      game_end_title_re: Regex::new(r"^\[[\d:]+\] \[[^\]]+\]: (?:\[System\] )?(?:\[CHAT\] )?\s*(VICTORY|GAME OVER)!?\s*$")
+        .unwrap(),
+      experience_re: Regex::new(r"\[CHAT\] \+\d+ Bed Wars Experience(?: \((\w+)\))?").unwrap(),
+      // the json output of /locraw
+      locraw_re: Regex::new(r#"\[CHAT\] (\{"server":.*\})\s*$"#).unwrap(),
+      formatting_code_re: Regex::new(r"§[0-9a-fk-orA-FK-OR]").unwrap(),
+      // names in kill messages are followed by gray text
+      colored_name_re: Regex::new(r"§([0-9a-f])([A-Za-z0-9_]{1,16}) ?§7").unwrap(),
+      // every line starts with the time, e.g. "[18:30:05]"
+      time_re: Regex::new(r"^\[(\d{2}:\d{2}:\d{2})\]").unwrap(),
     }
-  } else if let Some(captures) = who_lobby_re.captures(line) {
-    ParsedLine::LobbyList {
-      usernames: captures[1].split(", ").map(|x| x.to_string()).collect(),
+  }
+
+  /// Names with their team color, from lines which still have the formatting codes.
+  /// E.g. "§cFoo §7was killed by §9Bar§7. §b§lFINAL KILL!"
+  fn get_team_colors(&self, line: &str) -> Vec<(String, Team)> {
+    self
+      .colored_name_re
+      .captures_iter(line)
+      .filter_map(|captures| {
+        let team = Team::from_color_code(captures[1].chars().next()?)?;
+        Some((captures[2].to_string(), team))
+      })
+      .collect()
+  }
+
+  /// When the line was written. The log only has the time, so it's from the last 24 hours
+  fn get_time(&self, line: &str) -> String {
+    let now = chrono::Local::now().naive_local();
+    let time = self
+      .time_re
+      .captures(line)
+      .and_then(|captures| chrono::NaiveTime::parse_from_str(&captures[1], "%H:%M:%S").ok());
+
+    let written = match time {
+      Some(time) if now.date().and_time(time) <= now => now.date().and_time(time),
+      Some(time) => (now.date() - chrono::Duration::days(1)).and_time(time),
+      None => now,
+    };
+    written.format("%Y-%m-%d %H:%M").to_string()
+  }
+
+  /// Removes the minecraft formatting codes (e.g. "§a"), which some clients write to the log
+  fn strip_formatting<'a>(&self, line: &'a str) -> Cow<'a, str> {
+    self.formatting_code_re.replace_all(line, "")
+  }
+
+  fn parse(&self, line: &str) -> ParsedLine {
+    let line = &*self.strip_formatting(line);
+
+    if let Some(captures) = self.joined_lobby_re.captures(line) {
+      ParsedLine::JoinedLobby {
+        username: captures[2].to_string(),
+        rank: captures.get(1).map(|x| x.as_str().to_string()),
+      }
+    } else if let Some(captures) = self.left_lobby_re.captures(line) {
+      ParsedLine::LeftLobby {
+        username: captures[1].to_string(),
+      }
+    } else if let Some(captures) = self.who_lobby_re.captures(line) {
+      ParsedLine::LobbyList {
+        usernames: captures[1].split(", ").map(strip_rank).collect(),
+      }
+    } else if self.game_start_re.is_match(line) {
+      ParsedLine::GameStart
+    } else if let Some(captures) = self.nick_re.captures(line) {
+      ParsedLine::Nick {
+        username: captures[1].to_string(),
+      }
+    } else if let Some(captures) = self.setting_user_re.captures(line) {
+      ParsedLine::SettingUser {
+        username: captures[1].to_string(),
+      }
+    } else if let Some(captures) = self.party_list_re.captures(line) {
+      ParsedLine::PartyList {
+        count: captures[1].parse().unwrap_or_default(),
+      }
+    } else if let Some(captures) = self.party_list_members_re.captures(line) {
+      ParsedLine::PartyMembers {
+        usernames: captures[1]
+          .split('●')
+          .filter(|x| !x.trim().is_empty())
+          .map(strip_rank)
+          .collect(),
+      }
+    } else if let Some(captures) = self.party_joined_re.captures(line) {
+      ParsedLine::PartyMembers {
+        usernames: vec![captures[1].to_string()],
+      }
+    } else if let Some(captures) = self.party_you_joined_re.captures(line) {
+      ParsedLine::PartyMembers {
+        usernames: vec![captures[1].to_string()],
+      }
+    } else if let Some(captures) = self.party_partying_with_re.captures(line) {
+      ParsedLine::PartyMembers {
+        usernames: captures[1].split(", ").map(strip_rank).collect(),
+      }
+    } else if let Some(captures) = self.party_left_re.captures(line) {
+      ParsedLine::PartyMemberLeft {
+        username: captures[1].to_string(),
+      }
+    } else if self.party_disbanded_re.is_match(line) {
+      ParsedLine::PartyDisbanded
+    } else if let Some(captures) = self.final_kill_re.captures(line) {
+      ParsedLine::FinalKill {
+        victim: captures[1].to_string(),
+        killer: self.killer_re.captures(&captures[2]).map(|c| c[1].to_string()),
+      }
+    } else if let Some(captures) = self.bed_destroyed_re.captures(line) {
+      ParsedLine::BedDestroyed {
+        team: Team::from_name(&captures[1]),
+        breaker: captures[2].to_string(),
+      }
+    } else if let Some(team) = self
+      .team_eliminated_re
+      .captures(line)
+      .and_then(|captures| Team::from_name(&captures[1]))
+    {
+      ParsedLine::TeamEliminated { team }
+    } else if self.first_killer_re.is_match(line) {
+      ParsedLine::GameEnd { result: None }
+    } else if let Some(captures) = self.game_end_title_re.captures(line) {
+      ParsedLine::GameEnd {
+        result: match &captures[1] {
+          "VICTORY" => Some(GameResult::Win),
+          _ => Some(GameResult::Loss),
+        },
+      }
+    } else if let Some(captures) = self.experience_re.captures(line) {
+      ParsedLine::GameEnd {
+        result: captures
+          .get(1)
+          .filter(|reason| reason.as_str() == "Win")
+          .map(|_| GameResult::Win),
+      }
+    } else if let Some(locraw) = self
+      .locraw_re
+      .captures(line)
+      .and_then(|captures| serde_json::from_str::<serde_json::Value>(&captures[1]).ok())
+    {
+      ParsedLine::Location {
+        location: Location {
+          mode: locraw["mode"].as_str().map(|x| x.to_string()),
+          map: locraw["map"].as_str().map(|x| x.to_string()),
+        },
+      }
+    } else if let Some(captures) = self.team_chat_re.captures(line) {
+      ParsedLine::TeamMembers {
+        members: Team::from_name(&captures[1])
+          .map(|team| vec![(captures[2].to_string(), team)])
+          .unwrap_or_default(),
+      }
+    } else {
+      ParsedLine::Nothing
     }
-  } else if game_start_re.is_match(line) {
-    ParsedLine::GameStart
-  } else {
-    ParsedLine::Nothing
   }
 }
 
-pub fn start_parsing_logs(data_arc: Arc<Mutex<crate::app::AppData>>) {
-  let log_file_path = crate::get_toml_value("settings.toml", "log_file")
-    .as_str()
-    .unwrap()
-    .to_string();
-
-  let file = File::open(log_file_path).expect("Log file not found");
-  let mut reader = BufReader::new(file);
-  let mut bytes: Vec<u8> = vec![];
+/// Runs the log parser and restarts it whenever it panics. Stops when the ui is gone
+pub fn supervise_parser(events: Sender<LobbyEvent>) {
+  let mut restarted = false;
 
   loop {
-    let byte_count = reader
-      .read_to_end(&mut bytes)
-      .expect("Reading bytes from the .log file went wrong");
-
-    if byte_count == 0 {
-      // eof
-      thread::sleep(Duration::from_millis(SLEEP_DURATION));
-      bytes.clear();
-      continue;
-    }
+    let parser_events = events.clone();
+    let result = thread::spawn(move || start_parsing_logs(parser_events, restarted)).join();
 
-    let data = data_arc.lock().unwrap();
+    let error = match result {
+      Ok(()) => return,
+      Err(panic) => panic
+        .downcast_ref::<&str>()
+        .map(|x| x.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown error".to_string()),
+    };
+    println!("The log parser crashed, restarting: {}", error);
 
-    if data.settings.paused {
-      continue;
+    if events.send(LobbyEvent::ParserCrashed(error)).is_err() {
+      return;
     }
 
-    drop(data);
+    thread::sleep(RESTART_DELAY);
+    restarted = true;
+  }
+}
 
-    let text_to_eof = UTF_8
-      .decode(&bytes, encoding::DecoderTrap::Ignore)
-      .expect("Decoding to UTF-8 went wrong");
+/// Reads the log files and sends what happened to the ui. Returns when the ui is gone.
+/// After a restart the log files are read from the end, so the events from before the crash aren't sent again
+pub fn start_parsing_logs(events: Sender<LobbyEvent>, restarted: bool) {
+  let parser = LineParser::new();
+  let mut sources: Vec<LogSource> = vec![];
+  let mut watcher = LogWatcher::new();
 
-    for line in text_to_eof.lines() {
-      match parse_line(line) {
-        ParsedLine::JoinedLobby { username } => {
-          let data = data_arc.lock().unwrap();
-          if !data.settings.auto_join_active {
-            return;
-          }
+  scan_log_files(&mut sources, &mut watcher);
+  if sources.is_empty() {
+    panic!("Log file not found");
+  }
+  if restarted {
+    for source in sources.iter_mut() {
+      if let Err(e) = source.reader.seek(SeekFrom::End(0)) {
+        dbg!(e);
+      }
+      source.replaying = false;
+    }
+  }
+  let mut last_scan = Instant::now();
 
-          if !data
-            .players
-            .iter()
-            .any(|s| s.username.to_lowercase() == username.to_lowercase())
-          {
-            drop(data);
+  loop {
+    if last_scan.elapsed() >= SCAN_INTERVAL {
+      scan_log_files(&mut sources, &mut watcher);
+      last_scan = Instant::now();
+    }
 
-            let player = data::get_stats(&username); // takes some time
+    let mut read_anything = false;
 
-            let mut data = data_arc.lock().unwrap();
+    for source in sources.iter_mut() {
+      let byte_count = source
+        .reader
+        .read_to_end(&mut source.bytes)
+        .expect("Reading bytes from the .log file went wrong");
 
-            data.players.push(player);
-            println!("Added {}", username);
+      if byte_count == 0 {
+        // eof
+        source.bytes.clear();
+        continue;
+      }
 
-            if data.settings.auto_sort {
-              drop(data);
-              data::sort_players(data_arc.clone());
-            }
-          }
-        }
-        ParsedLine::LeftLobby { username } => {
-          let mut data = data_arc.lock().unwrap();
-          if !data.settings.auto_leave_active {
-            return;
-          }
+      let text_to_eof = UTF_8
+        .decode(&source.bytes, encoding::DecoderTrap::Ignore)
+        .expect("Decoding to UTF-8 went wrong");
+      source.bytes.clear();
 
-          if let Some((index, _)) = data.players.iter().find_position(|s| s.username == username) {
-            data.players.remove(index);
-            println!("Removed {}", username);
+      for line in text_to_eof.lines() {
+        let mut parsed_lines = vec![];
 
-            if data.settings.auto_sort {
-              drop(data);
-              data::sort_players(data_arc.clone());
-            }
-          }
+        // the formatting codes are stripped when parsing, so the team colors have to be read first
+        let team_members = parser.get_team_colors(line);
+        if !team_members.is_empty() {
+          parsed_lines.push(ParsedLine::TeamMembers { members: team_members });
         }
-        ParsedLine::LobbyList { usernames } => {
-          let mut data = data_arc.lock().unwrap();
+        parsed_lines.push(parser.parse(line));
 
-          if data.settings.auto_clear_on_who {
-            data.players.clear();
+        for parsed_line in parsed_lines {
+          if parsed_line == ParsedLine::Nothing {
+            continue;
           }
 
-          if !data.settings.auto_add_on_who {
+          read_anything = true;
+          let event = LogEvent {
+            parsed_line,
+            source: source.path.display().to_string(),
+            time: parser.get_time(line),
+            backlog: source.replaying,
+          };
+          if events.send(LobbyEvent::Log(event)).is_err() {
             return;
           }
+        }
+      }
+
+      // the first read goes to the end of the file
+      source.replaying = false;
+    }
+
+    if events.send(LobbyEvent::Heartbeat).is_err() {
+      return;
+    }
+
+    if !read_anything {
+      watcher.wait();
+    }
+  }
+}
 
-          drop(data);
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-          usernames.par_iter().for_each(|username| {
-            let data = data_arc.lock().unwrap();
-            if data
-              .players
-              .iter()
-              .any(|p| p.username.to_lowercase() == username.to_lowercase())
-            {
-              // don't add players which are already added
-              return;
-            }
+  fn parse(line: &str) -> ParsedLine {
+    LineParser::new().parse(line)
+  }
 
-            drop(data);
+  fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|x| x.to_string()).collect()
+  }
 
-            let player = data::get_stats(username); // takes some time
+  #[test]
+  fn lobby_list() {
+    let line = std::fs::read_to_string("test.log").unwrap();
 
-            let mut data = data_arc.lock().unwrap();
+    assert_eq!(
+      parse(line.lines().next().unwrap()),
+      ParsedLine::LobbyList {
+        usernames: names(&[
+          "Luj8n",
+          "Hypixel",
+          "Centranos",
+          "Skeppy",
+          "MartinLV",
+          "ggimoji",
+          "notahypickleplayer",
+          "Breefing"
+        ])
+      }
+    );
+  }
 
-            data.players.push(player);
-            println!("Added {}", username);
-          });
+  #[test]
+  fn joined_and_left_lobby() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo_1 has joined (3/8)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo_1".to_string(),
+        rank: None
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo_1 has quit!"),
+      ParsedLine::LeftLobby {
+        username: "Foo_1".to_string()
+      }
+    );
+  }
 
-          let data = data_arc.lock().unwrap();
+  #[test]
+  fn joined_lobby_with_rank() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [MVP+] Foo has joined (3/8)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo".to_string(),
+        rank: Some("MVP+".to_string())
+      }
+    );
+  }
 
-          if data.settings.auto_sort {
-            drop(data);
-            data::sort_players(data_arc.clone());
-          }
-        }
-        ParsedLine::GameStart => {
-          println!("Game has started");
+  #[test]
+  fn chat_lines_from_other_clients() {
+    // newer clients (e.g. lunar on 1.20) log chat as "[System] [CHAT]"
+    assert_eq!(
+      parse("[00:00:00] [Render thread/INFO]: [System] [CHAT] Foo has joined (3/8)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo".to_string(),
+        rank: None
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [main/INFO]: [CHAT] Foo has quit!"),
+      ParsedLine::LeftLobby {
+        username: "Foo".to_string()
+      }
+    );
+  }
+
+  #[test]
+  fn formatting_codes() {
+    // vanilla writes chat without formatting codes
+    assert_eq!(
+      parse("[12:34:56] [Client thread/INFO]: [CHAT] [VIP] Foo has joined (2/8)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo".to_string(),
+        rank: Some("VIP".to_string())
+      }
+    );
+    // lunar
+    assert_eq!(
+      parse("[12:34:56] [Client thread/INFO]: [CHAT] §a[VIP] Foo§e has joined (§b2§e/§b8§e)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo".to_string(),
+        rank: Some("VIP".to_string())
+      }
+    );
+    // badlion
+    assert_eq!(
+      parse("[12:34:56] [main/INFO]: [CHAT] §b[MVP§c+§b] Foo §ehas joined (§b2§e/§b8§e)!"),
+      ParsedLine::JoinedLobby {
+        username: "Foo".to_string(),
+        rank: Some("MVP+".to_string())
+      }
+    );
+    assert_eq!(
+      parse("[12:34:56] [Client thread/INFO]: [CHAT] §bONLINE: §7Foo§f, §a[VIP] Bar§f, §7Baz"),
+      ParsedLine::LobbyList {
+        usernames: names(&["Foo", "Bar", "Baz"])
+      }
+    );
+    assert_eq!(
+      parse("[12:34:56] [Client thread/INFO]: [CHAT] §cFoo §7was killed by §9Bar§7. §b§lFINAL KILL!"),
+      ParsedLine::FinalKill {
+        victim: "Foo".to_string(),
+        killer: Some("Bar".to_string())
+      }
+    );
+  }
+
+  #[test]
+  fn ranks() {
+    assert_eq!(
+      split_rank("[MVP++] Foo"),
+      (Some("MVP++".to_string()), "Foo".to_string())
+    );
+    assert_eq!(split_rank("Foo "), (None, "Foo".to_string()));
+  }
+
+  #[test]
+  fn chat_messages_are_not_events() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo: Bar has joined (3/8)!"),
+      ParsedLine::Nothing
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [RED] Foo: lol joined the party."),
+      ParsedLine::TeamMembers {
+        members: vec![("Foo".to_string(), Team::Red)]
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: Foo has joined (3/8)!"),
+      ParsedLine::Nothing
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [SHOUT] [RED] Foo: x]: GAME OVER!"),
+      ParsedLine::TeamMembers {
+        members: vec![("Foo".to_string(), Team::Red)]
+      }
+    );
+  }
+
+  #[test]
+  fn game_start() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] The game starts in 1 seconds!"),
+      ParsedLine::GameStart
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] The game starts in 10 seconds!"),
+      ParsedLine::Nothing
+    );
+  }
+
+  #[test]
+  fn nick_and_setting_user() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: Nick: Foo"),
+      ParsedLine::Nick {
+        username: "Foo".to_string()
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: Setting user: Luj8n"),
+      ParsedLine::SettingUser {
+        username: "Luj8n".to_string()
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo: lol]: Setting user: Bar"),
+      ParsedLine::Nothing
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo: lol]: Nick: Bar"),
+      ParsedLine::Nothing
+    );
+  }
+
+  #[test]
+  fn party() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Party Members (3)"),
+      ParsedLine::PartyList { count: 3 }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Party Leader: [MVP+] Foo ●"),
+      ParsedLine::PartyMembers {
+        usernames: names(&["Foo"])
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Party Members: Bar ● [VIP] Baz ●"),
+      ParsedLine::PartyMembers {
+        usernames: names(&["Bar", "Baz"])
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [VIP] Bar joined the party."),
+      ParsedLine::PartyMembers {
+        usernames: names(&["Bar"])
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] You have joined [MVP++] Foo's party!"),
+      ParsedLine::PartyMembers {
+        usernames: names(&["Foo"])
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] You'll be partying with: [VIP] Bar, Baz"),
+      ParsedLine::PartyMembers {
+        usernames: names(&["Bar", "Baz"])
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [VIP] Bar has left the party."),
+      ParsedLine::PartyMemberLeft {
+        username: "Bar".to_string()
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] You left the party."),
+      ParsedLine::PartyDisbanded
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [MVP+] Foo has disbanded the party!"),
+      ParsedLine::PartyDisbanded
+    );
+    // anyone can say it in chat
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [MVP+] Foo: Bar has disbanded the party!"),
+      ParsedLine::Nothing
+    );
+  }
+
+  #[test]
+  fn teams() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] [SHOUT] [123✫] [BLUE] [MVP+] Foo: hi"),
+      ParsedLine::TeamMembers {
+        members: vec![("Foo".to_string(), Team::Blue)]
+      }
+    );
+    assert_eq!(
+      LineParser::new().get_team_colors("[00:00:00] [Client thread/INFO]: [CHAT] §cFoo §7was shot by §9Bar§7."),
+      vec![("Foo".to_string(), Team::Red), ("Bar".to_string(), Team::Blue)]
+    );
+  }
+
+  #[test]
+  fn match_events() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo was knocked into the void by Bar. FINAL KILL!"),
+      ParsedLine::FinalKill {
+        victim: "Foo".to_string(),
+        killer: Some("Bar".to_string())
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] Foo fell into the void. FINAL KILL!"),
+      ParsedLine::FinalKill {
+        victim: "Foo".to_string(),
+        killer: None
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] BED DESTRUCTION > Red Bed was destroyed by Bar!"),
+      ParsedLine::BedDestroyed {
+        team: Some(Team::Red),
+        breaker: "Bar".to_string()
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] BED DESTRUCTION > Your Bed was iced by Bar!"),
+      ParsedLine::BedDestroyed {
+        team: None,
+        breaker: "Bar".to_string()
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] TEAM ELIMINATED > Green Team has been eliminated!"),
+      ParsedLine::TeamEliminated { team: Team::Green }
+    );
+  }
+
+  #[test]
+  fn game_end() {
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT]                   1st Killer - [MVP+] Foo - 7"),
+      ParsedLine::GameEnd { result: None }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] VICTORY!"),
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Win)
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] GAME OVER!"),
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Loss)
+      }
+    );
+    assert_eq!(
+      parse("[00:00:00] [Client thread/INFO]: [CHAT] +50 Bed Wars Experience (Win)"),
+      ParsedLine::GameEnd {
+        result: Some(GameResult::Win)
+      }
+    );
+  }
+
+  #[test]
+  fn location() {
+    assert_eq!(
+      parse(
+        r#"[00:00:00] [Client thread/INFO]: [CHAT] {"server":"mini123A","gametype":"BEDWARS","mode":"BEDWARS_EIGHT_TWO","map":"Lighthouse"}"#
+      ),
+      ParsedLine::Location {
+        location: Location {
+          mode: Some("BEDWARS_EIGHT_TWO".to_string()),
+          map: Some("Lighthouse".to_string())
         }
-        ParsedLine::Nothing => {}
       }
-    }
-    bytes.clear();
+    );
+  }
+
+  #[test]
+  fn line_time() {
+    let time = LineParser::new().get_time("[23:59:30] [Client thread/INFO]: [CHAT] Foo has joined (3/8)!");
+    assert!(time.ends_with(" 23:59"));
+    assert!(time.as_str() <= chrono::Local::now().format("%Y-%m-%d %H:%M").to_string().as_str());
   }
 }
@@ -0,0 +1,66 @@
+use crate::fetching;
+use reqwest::Url;
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:8090";
+
+type Fetch = fn(String) -> Result<serde_json::Value, String>;
+
+/// `jahbo serve`: a small http api mirroring hypixel's /v2/player, /v2/guild and /v2/status, backed by our api key and the fetch
+/// cache. Other jahbo instances use it by setting hypixel_url to its address
+pub fn serve() {
+  let address = crate::get_string_setting("serve_address", DEFAULT_SERVE_ADDRESS);
+
+  let server = Server::http(&address).unwrap_or_else(|e| panic!("Couldn't listen on {}: {}", address, e));
+  println!("Serving on http://{}", address);
+
+  for request in server.incoming_requests() {
+    thread::spawn(move || handle_request(request));
+  }
+}
+
+fn handle_request(request: Request) {
+  // the url of the request is only the path and the query
+  let Ok(url) = Url::parse(&format!("http://localhost{}", request.url())) else {
+    respond(request, 400, Err("Invalid url".to_string()));
+    return;
+  };
+
+  let query = |key: &str| url.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v.to_string());
+
+  let (field, fetch): (&str, Fetch) = match url.path().trim_start_matches("/v2") {
+    "/player" => ("uuid", fetching::get_game_stats),
+    "/guild" => ("player", fetching::get_guild),
+    "/status" => ("uuid", fetching::get_status),
+    _ => {
+      respond(request, 404, Err("Not found".to_string()));
+      return;
+    }
+  };
+
+  let Some(uuid) = query(field) else {
+    respond(request, 400, Err(format!("Missing one or more fields [{}]", field)));
+    return;
+  };
+
+  let result = fetch(uuid);
+
+  println!("{} {}", request.method(), request.url());
+
+  // errors from hypixel (or the network) are passed on as a bad gateway
+  let status = if result.is_ok() { 200 } else { 502 };
+  respond(request, status, result);
+}
+
+fn respond(request: Request, status: u16, result: Result<serde_json::Value, String>) {
+  let body = result.unwrap_or_else(|cause| serde_json::json!({ "success": false, "cause": cause }));
+
+  let response = Response::from_string(body.to_string())
+    .with_status_code(status)
+    .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+
+  if let Err(e) = request.respond(response) {
+    dbg!(e);
+  }
+}
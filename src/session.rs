@@ -0,0 +1,143 @@
+use crate::data::PlayerStats;
+use crate::game::GameResult;
+use serde::{Deserialize, Serialize};
+use std::fs::write;
+
+const SESSIONS_FILE: &str = "sessions.json";
+
+/// The counters of our own stats which the session deltas are calculated from
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)] // so counters added later don't make the saved sessions unreadable
+pub struct StatsSnapshot {
+  pub games_played: i64,
+  pub wins: i64,
+  pub losses: i64,
+  pub final_kills: i64,
+  pub final_deaths: i64,
+  pub beds_broken: i64,
+  pub bedwars_level: i64,
+}
+
+impl StatsSnapshot {
+  pub fn from_stats(player: &PlayerStats) -> Option<Self> {
+    if !player.has_data() {
+      return None;
+    }
+
+    Some(Self {
+      games_played: player.games_played_bedwars.unwrap_or(0),
+      wins: player.wins_bedwars.unwrap_or(0),
+      losses: player.losses_bedwars.unwrap_or(0),
+      final_kills: player.final_kills_bedwars.unwrap_or(0),
+      final_deaths: player.final_deaths_bedwars.unwrap_or(0),
+      beds_broken: player.beds_broken_bedwars.unwrap_or(0),
+      bedwars_level: player.bedwars_level.unwrap_or(0),
+    })
+  }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+  pub username: String,
+  pub started: String,
+  pub updated: String,
+  pub start: StatsSnapshot,
+  pub latest: StatsSnapshot,
+}
+
+impl Session {
+  /// What changed since the start of the session
+  pub fn delta(&self) -> StatsSnapshot {
+    StatsSnapshot {
+      games_played: self.latest.games_played - self.start.games_played,
+      wins: self.latest.wins - self.start.wins,
+      losses: self.latest.losses - self.start.losses,
+      final_kills: self.latest.final_kills - self.start.final_kills,
+      final_deaths: self.latest.final_deaths - self.start.final_deaths,
+      beds_broken: self.latest.beds_broken - self.start.beds_broken,
+      bedwars_level: self.latest.bedwars_level - self.start.bedwars_level,
+    }
+  }
+}
+
+/// Ratio that doesn't divide by 0, like hypixel shows it
+pub fn ratio(numerator: i64, denominator: i64) -> f64 {
+  numerator as f64 / denominator.max(1) as f64
+}
+
+fn now() -> String {
+  chrono::Local::now().format("%Y-%m-%d %H:%M").to_string()
+}
+
+/// Our own stats since the session was started. Past sessions are saved to `sessions.json`
+#[derive(Debug, Default)]
+pub struct SessionTracker {
+  // games seen in the log, which update before the api does
+  pub wins: u32,
+  pub losses: u32,
+
+  pub current: Option<Session>,
+  pub past: Vec<Session>,
+}
+
+impl SessionTracker {
+  pub fn load() -> Self {
+    Self {
+      past: crate::load_json_file(SESSIONS_FILE),
+      ..Default::default()
+    }
+  }
+
+  fn save(&self) {
+    let sessions = self.past.iter().chain(self.current.iter()).collect::<Vec<_>>();
+    let json = serde_json::to_string_pretty(&sessions).expect("Serializing the sessions went wrong");
+
+    if let Err(e) = write(SESSIONS_FILE, json) {
+      dbg!(e);
+    }
+  }
+
+  pub fn add_result(&mut self, result: GameResult) {
+    match result {
+      GameResult::Win => self.wins += 1,
+      GameResult::Loss => self.losses += 1,
+    }
+  }
+
+  /// Ends the current session (if there is one) and starts a new one from the given stats
+  pub fn start(&mut self, player: &PlayerStats) {
+    let Some(snapshot) = StatsSnapshot::from_stats(player) else {
+      return;
+    };
+
+    if let Some(session) = self.current.take() {
+      self.past.push(session);
+    }
+
+    self.wins = 0;
+    self.losses = 0;
+    self.current = Some(Session {
+      username: player.username.clone(),
+      started: now(),
+      updated: now(),
+      start: snapshot.clone(),
+      latest: snapshot,
+    });
+    self.save();
+  }
+
+  pub fn update(&mut self, player: &PlayerStats) {
+    let Some(snapshot) = StatsSnapshot::from_stats(player) else {
+      return;
+    };
+
+    match &mut self.current {
+      Some(session) if session.username.to_lowercase() == player.username.to_lowercase() => {
+        session.latest = snapshot;
+        session.updated = now();
+        self.save();
+      }
+      _ => self.start(player),
+    }
+  }
+}
@@ -0,0 +1,51 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+// how often to poll when file change notifications don't work
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// notifications can be missed (e.g. on network drives), so check the files once in a while anyway
+const WATCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Waits for the log files to change, with inotify on linux (and the native notifications elsewhere).
+/// Falls back to polling if a file can't be watched.
+pub struct LogWatcher {
+  watcher: Option<RecommendedWatcher>,
+  events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl LogWatcher {
+  pub fn new() -> Self {
+    let (sender, events) = channel();
+
+    let watcher = notify::recommended_watcher(sender)
+      .map_err(|e| println!("Couldn't watch the log files, polling instead: {}", e))
+      .ok();
+
+    Self { watcher, events }
+  }
+
+  pub fn watch(&mut self, path: &Path) {
+    if let Some(watcher) = &mut self.watcher {
+      if let Err(e) = watcher.watch(path, RecursiveMode::NonRecursive) {
+        println!("Couldn't watch {}, polling instead: {}", path.display(), e);
+        self.watcher = None;
+      }
+    }
+  }
+
+  /// Blocks until a watched file changes (or until it's time to poll)
+  pub fn wait(&self) {
+    if self.watcher.is_none() {
+      thread::sleep(POLL_INTERVAL);
+      return;
+    }
+
+    if self.events.recv_timeout(WATCH_TIMEOUT).is_ok() {
+      // one write can cause multiple events
+      while self.events.try_recv().is_ok() {}
+    }
+  }
+}